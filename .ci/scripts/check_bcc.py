@@ -17,10 +17,7 @@
 from bcc import BPF
 
 file = open('src/bpf/exec_logger.c', 'r')
-bpf_text = file.read()
-bpf_text = bpf_text.replace("MAX_ARGS", "20")
-bpf_text = bpf_text.replace("ANCESTOR_NAME", "sshd")
-bpf_text = bpf_text.replace("MAX_ANCESTORS", "20")
+bpf_text = "#define MAX_ARGS 20\n#define MAX_ARG_LEN 1024\n#define ARGS_BUDGET 8192\n#define MAX_ANCESTORS 20\n#define MAX_ENVS 20\n#define ATTACH_KPROBE\n" + file.read()
 
 b = BPF(text=bpf_text)
 
@@ -0,0 +1,248 @@
+// Copyright 2020 Lukas Pustina
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Assembles the events of the BPF program into the records passed to outputs.
+
+use crate::env::EnvOpts;
+use crate::output::Output;
+use crate::{bpf, Dropped, Exit, ExitMode, Result, Return};
+use log::debug;
+use std::collections::{BTreeSet, HashMap};
+use std::time::{Duration, SystemTime};
+
+/// Exec with its metadata and arguments as passed to `Output::exec`.
+#[derive(Debug)]
+pub struct ExecEvent {
+    pub exec: Return,
+    /// Exit of the started process in `ExitMode::Merged`; `None` if it is still running or exits are reported
+    /// separately
+    pub exit: Option<Exit>,
+    /// Set if the exec has been evicted from the pending execs before its exit arrived, cf.
+    /// `ExecAssembler::max_pending`, or if the kernel missed its return, cf. `bpf::Event::Incomplete`
+    pub incomplete: bool,
+}
+
+impl ExecEvent {
+    pub fn new(exec: Return) -> ExecEvent {
+        ExecEvent {
+            exec,
+            exit: None,
+            incomplete: false,
+        }
+    }
+
+    pub fn exit(mut self, exit: Exit) -> ExecEvent {
+        self.exit = Some(exit);
+        self
+    }
+
+    pub fn incomplete(mut self) -> ExecEvent {
+        self.incomplete = true;
+        self
+    }
+}
+
+/// Turns events of the BPF program into records for an `Output`.
+///
+/// In `ExitMode::Merged`, successful execs are held back until their process exits. If the exit is lost, e.g., because
+/// the kernel dropped the event, the exec is evicted after `pending_ttl` or when more than `max_pending` execs are
+/// pending, and reported as incomplete.
+#[derive(Debug)]
+pub struct ExecAssembler {
+    ancestor_ids: bpf::AncestorIds,
+    env_opts: EnvOpts,
+    exits: Option<ExitMode>,
+    pending: PendingExecs,
+    max_pending: usize,
+    pending_ttl: Duration,
+    procfs: bool,
+    started: bool,
+    evicted: u64,
+    dropped: u64,
+}
+
+impl ExecAssembler {
+    pub const DEFAULT_MAX_PENDING: usize = 16384;
+    pub const DEFAULT_PENDING_TTL: Duration = Duration::from_secs(3600);
+
+    pub fn new(ancestor_ids: bpf::AncestorIds, env_opts: EnvOpts, exits: Option<ExitMode>) -> ExecAssembler {
+        ExecAssembler {
+            ancestor_ids,
+            env_opts,
+            exits,
+            pending: PendingExecs::default(),
+            max_pending: Self::DEFAULT_MAX_PENDING,
+            pending_ttl: Self::DEFAULT_PENDING_TTL,
+            procfs: false,
+            started: false,
+            evicted: 0,
+            dropped: 0,
+        }
+    }
+
+    /// Sets the max number of execs waiting for their exit; the oldest ones are evicted first
+    pub fn max_pending(mut self, max_pending: usize) -> Self {
+        self.max_pending = max_pending;
+        self
+    }
+
+    /// Sets how long an exec waits for its exit measured by the time of later events
+    pub fn pending_ttl(mut self, pending_ttl: Duration) -> Self {
+        self.pending_ttl = pending_ttl;
+        self
+    }
+
+    /// Allows resolving incomplete cwds from procfs; only set this for live events, cf. `EventSource::live`
+    pub fn procfs(mut self, procfs: bool) -> Self {
+        self.procfs = procfs;
+        self
+    }
+
+    /// Number of execs evicted so far
+    pub fn evicted(&self) -> u64 {
+        self.evicted
+    }
+
+    /// Number of execs dropped by the kernel or whose return it missed so far
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+
+    /// Passes the records completed by `event` to `output`; malformed events are ignored.
+    pub fn push<O: Output + ?Sized>(&mut self, event: bpf::Event, output: &mut O) -> Result<()> {
+        match event {
+            bpf::Event::Exec(exec) => {
+                let r = Return::from_exec(exec, &self.ancestor_ids, &self.env_opts, self.procfs);
+                debug!("Return event: {:?}", r);
+                self.start(r.timestamp, output)?;
+                if self.exits == Some(ExitMode::Merged) && r.ret_val == 0 {
+                    let now = r.timestamp;
+                    if let Some(replaced) = self.pending.insert(r) {
+                        output.exec(ExecEvent::new(replaced))?;
+                    }
+                    self.evict(now, output)
+                } else {
+                    output.exec(ExecEvent::new(r))
+                }
+            }
+            bpf::Event::Exit(exit) => {
+                let exit = Exit::from_exit(exit, &self.ancestor_ids);
+                debug!("Exit event: {:?}", exit);
+                self.start(exit.timestamp, output)?;
+                let now = exit.timestamp;
+                match self.pending.complete(&exit) {
+                    Some(r) => output.exec(ExecEvent::new(r).exit(exit))?,
+                    None => output.exit(exit)?,
+                }
+                self.evict(now, output)
+            }
+            bpf::Event::Incomplete(exec) => {
+                let r = Return::from_exec(exec, &self.ancestor_ids, &self.env_opts, false);
+                debug!("Incomplete event: {:?}", r);
+                self.start(r.timestamp, output)?;
+                self.dropped += 1;
+                output.exec(ExecEvent::new(r).incomplete())
+            }
+            bpf::Event::Dropped(dropped) => {
+                self.dropped += dropped.count;
+                output.dropped(Dropped::from(dropped))
+            }
+            bpf::Event::Malformed(_) => Ok(()),
+        }
+    }
+
+    /// Passes the time of the first event to `output`, cf. `Output::start`.
+    fn start<O: Output + ?Sized>(&mut self, time: SystemTime, output: &mut O) -> Result<()> {
+        if self.started {
+            return Ok(());
+        }
+        self.started = true;
+        output.start(time)
+    }
+
+    /// Reports execs started more than `pending_ttl` before `now` and the oldest ones beyond `max_pending`.
+    fn evict<O: Output + ?Sized>(&mut self, now: SystemTime, output: &mut O) -> Result<()> {
+        let expired = now.checked_sub(self.pending_ttl).unwrap_or(SystemTime::UNIX_EPOCH);
+        let max_pending = self.max_pending;
+        while let Some(r) = self
+            .pending
+            .pop_oldest(|timestamp, len| timestamp < expired || len > max_pending)
+        {
+            debug!("Evicted pending exec: {:?}", r);
+            self.evicted += 1;
+            output.exec(ExecEvent::new(r).incomplete())?;
+        }
+        Ok(())
+    }
+
+    /// Passes the execs of processes still running to `output`.
+    pub fn finish<O: Output + ?Sized>(&mut self, output: &mut O) -> Result<()> {
+        for r in self.pending.drain() {
+            output.exec(ExecEvent::new(r))?;
+        }
+        Ok(())
+    }
+}
+
+/// Successful execs waiting for the exit of their process in `ExitMode::Merged`.
+///
+/// Execs are keyed by pid, because exits are reported per process and a thread exec'ing continues as pid.
+#[derive(Debug, Default)]
+struct PendingExecs {
+    execs: HashMap<u32, Return>,
+    /// Start and pid of the pending execs ordered by start
+    starts: BTreeSet<(SystemTime, u32)>,
+}
+
+impl PendingExecs {
+    /// Returns the exec pending for the same pid before, whose program has been replaced.
+    fn insert(&mut self, ret: Return) -> Option<Return> {
+        let start = (ret.timestamp, ret.pid);
+        let replaced = self.execs.insert(ret.pid, ret);
+        if let Some(replaced) = &replaced {
+            self.starts.remove(&(replaced.timestamp, replaced.pid));
+        }
+        self.starts.insert(start);
+        replaced
+    }
+
+    /// Returns the exec which started the exited process.
+    fn complete(&mut self, exit: &Exit) -> Option<Return> {
+        match self.execs.get(&exit.pid) {
+            Some(ret) if ret.timestamp == exit.exec_timestamp => {
+                self.starts.remove(&(exit.exec_timestamp, exit.pid));
+                self.execs.remove(&exit.pid)
+            }
+            _ => None,
+        }
+    }
+
+    /// Removes the oldest exec if `evict` holds for its start and the number of pending execs.
+    fn pop_oldest<F: Fn(SystemTime, usize) -> bool>(&mut self, evict: F) -> Option<Return> {
+        let &(timestamp, pid) = self.starts.iter().next()?;
+        if !evict(timestamp, self.execs.len()) {
+            return None;
+        }
+        self.starts.remove(&(timestamp, pid));
+        self.execs.remove(&pid)
+    }
+
+    /// Returns all execs of processes still running ordered by start.
+    fn drain(&mut self) -> Vec<Return> {
+        self.starts.clear();
+        let mut execs: Vec<_> = self.execs.drain().map(|(_, ret)| ret).collect();
+        execs.sort_by_key(|x| x.timestamp);
+        execs
+    }
+}
@@ -0,0 +1,190 @@
+// Copyright 2020 Lukas Pustina
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::{Error, Result};
+use serde::Serialize;
+use std::fmt;
+use std::str::FromStr;
+
+/// Max number of ancestor names, cf. exec_logger.c
+pub const MAX_ANCESTOR_NAMES: usize = 64;
+/// Ancestor name looked for by default; a prefix so that `sshd-session` of OpenSSH 9.8 and later matches as well
+pub const DEFAULT_ANCESTOR: &str = "sshd*";
+/// Length of comm including the terminating 0, cf. linux/sched.h
+const TASK_COMM_LEN: usize = 16;
+const ANCESTOR_EXACT: u32 = 0;
+
+/// Defines how an ancestor name is compared to the comm of an ancestor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MatchMode {
+    Exact,
+    Prefix,
+}
+
+/// Name of an ancestor to look for, e.g., `sshd`.
+///
+/// Parses from `NAME[*][=LABEL]`; a trailing `*` selects prefix matching.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AncestorName {
+    pub name: String,
+    pub match_mode: MatchMode,
+    /// Reported with matches to classify execs, e.g., "ssh"
+    pub label: Option<String>,
+}
+
+impl AncestorName {
+    pub fn new<T: Into<String>>(name: T) -> AncestorName {
+        AncestorName {
+            name: name.into(),
+            match_mode: MatchMode::Exact,
+            label: None,
+        }
+    }
+
+    pub fn match_mode(mut self, match_mode: MatchMode) -> AncestorName {
+        self.match_mode = match_mode;
+        self
+    }
+
+    pub fn label<T: Into<String>>(mut self, label: T) -> AncestorName {
+        self.label = Some(label.into());
+        self
+    }
+
+    /// Checks that the name can be matched against a comm.
+    pub fn validate(&self) -> Result<()> {
+        if self.name.is_empty() || self.name.contains('\0') {
+            return Err(Error::InvalidAncestorName {
+                name: self.name.clone(),
+            });
+        }
+        if self.name.len() > TASK_COMM_LEN - 1 {
+            return Err(Error::AncestorNameTooLong {
+                name: self.name.clone(),
+                max: TASK_COMM_LEN - 1,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Key of the BPF map `ancestor_names`, cf. `struct ancestor_key_t` in exec_logger.c
+    pub(crate) fn key(&self) -> Vec<u8> {
+        let len = match self.match_mode {
+            MatchMode::Exact => ANCESTOR_EXACT,
+            MatchMode::Prefix => self.name.len() as u32,
+        };
+        // Length has been checked by validate
+        let mut name = [0u8; TASK_COMM_LEN];
+        let bytes = self.name.as_bytes();
+        let n = bytes.len().min(TASK_COMM_LEN - 1);
+        name[..n].copy_from_slice(&bytes[..n]);
+
+        let mut key = len.to_ne_bytes().to_vec();
+        key.extend_from_slice(&name);
+        key
+    }
+}
+
+impl fmt::Display for AncestorName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.name)?;
+        if self.match_mode == MatchMode::Prefix {
+            f.write_str("*")?;
+        }
+        if let Some(label) = &self.label {
+            write!(f, "={}", label)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for AncestorName {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (name, label) = match s.find('=') {
+            Some(pos) => (&s[..pos], Some(&s[pos + 1..])),
+            None => (s, None),
+        };
+        let (name, match_mode) = match name.strip_suffix('*') {
+            Some(prefix) => (prefix, MatchMode::Prefix),
+            None => (name, MatchMode::Exact),
+        };
+        let ancestor_name = AncestorName::new(name).match_mode(match_mode);
+        let ancestor_name = match label {
+            Some(label) => ancestor_name.label(label),
+            None => ancestor_name,
+        };
+        ancestor_name.validate()?;
+
+        Ok(ancestor_name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_ancestor_name() {
+        assert_eq!("sshd".parse::<AncestorName>().unwrap(), AncestorName::new("sshd"));
+        assert_eq!(
+            "ssh*=remote".parse::<AncestorName>().unwrap(),
+            AncestorName::new("ssh").match_mode(MatchMode::Prefix).label("remote")
+        );
+        // Only a trailing `*` selects prefix matching
+        assert_eq!("a*b".parse::<AncestorName>().unwrap(), AncestorName::new("a*b"));
+        assert_eq!(
+            "cron=".parse::<AncestorName>().unwrap(),
+            AncestorName::new("cron").label("")
+        );
+        for s in ["sshd", "ssh*", "ssh*=remote", "cron=batch"] {
+            assert_eq!(s.parse::<AncestorName>().unwrap().to_string(), s);
+        }
+    }
+
+    #[test]
+    fn parse_invalid_ancestor_name() {
+        for s in ["", "*", "=label", "*=label", "a\0b"] {
+            assert!(
+                matches!(s.parse::<AncestorName>(), Err(Error::InvalidAncestorName { .. })),
+                "{:?}",
+                s
+            );
+        }
+        assert!(matches!(
+            "a-very-long-name".parse::<AncestorName>(),
+            Err(Error::AncestorNameTooLong { max: 15, .. })
+        ));
+        assert!("a-15-chars-name".parse::<AncestorName>().is_ok());
+    }
+
+    #[test]
+    fn key_like_ancestor_key_t() {
+        let exact = AncestorName::new("sshd").key();
+        let mut expected = ANCESTOR_EXACT.to_ne_bytes().to_vec();
+        expected.extend_from_slice(b"sshd\0\0\0\0\0\0\0\0\0\0\0\0");
+        assert_eq!(exact, expected);
+
+        let prefix = AncestorName::new("ssh").match_mode(MatchMode::Prefix).key();
+        let mut expected = 3u32.to_ne_bytes().to_vec();
+        expected.extend_from_slice(b"ssh\0\0\0\0\0\0\0\0\0\0\0\0\0");
+        assert_eq!(prefix, expected);
+
+        // Labels do not change the key
+        assert_eq!(AncestorName::new("sshd").label("ssh").key(), exact);
+    }
+}
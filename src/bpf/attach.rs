@@ -0,0 +1,130 @@
+// Copyright 2020 Lukas Pustina
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::{Error, Result};
+use bcc::BPF;
+use std::fmt;
+use std::str::FromStr;
+
+/// Defines how the BPF program hooks into exec.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AttachStrategy {
+    /// Tracepoints `syscalls:sys_{enter,exit}_execve` and `syscalls:sys_{enter,exit}_execveat`
+    SyscallTracepoints,
+    /// Tracepoint `sched:sched_process_exec`; sees successful execs only
+    SchedTracepoint,
+    /// Kprobes and Kretprobes on the kernel's execve and execveat syscall functions
+    Kprobe,
+}
+
+impl AttachStrategy {
+    /// All strategies in the order they are tried if none has been selected explicitly.
+    pub const ALL: [AttachStrategy; 3] = [
+        AttachStrategy::SyscallTracepoints,
+        AttachStrategy::SchedTracepoint,
+        AttachStrategy::Kprobe,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            AttachStrategy::SyscallTracepoints => "syscalls",
+            AttachStrategy::SchedTracepoint => "sched",
+            AttachStrategy::Kprobe => "kprobe",
+        }
+    }
+
+    /// Preprocessor define which enables the corresponding handlers in exec_logger.c
+    pub(crate) fn define(&self) -> &'static str {
+        match self {
+            AttachStrategy::SyscallTracepoints => "ATTACH_SYSCALL_TRACEPOINTS",
+            AttachStrategy::SchedTracepoint => "ATTACH_SCHED_TRACEPOINT",
+            AttachStrategy::Kprobe => "ATTACH_KPROBE",
+        }
+    }
+
+    pub(crate) fn attach(&self, module: &mut BPF) -> Result<()> {
+        match self {
+            AttachStrategy::SyscallTracepoints => {
+                bcc::Tracepoint::new()
+                    .handler("tracepoint__syscalls__sys_enter_execve")
+                    .subsystem("syscalls")
+                    .tracepoint("sys_enter_execve")
+                    .attach(module)?;
+                bcc::Tracepoint::new()
+                    .handler("tracepoint__syscalls__sys_exit_execve")
+                    .subsystem("syscalls")
+                    .tracepoint("sys_exit_execve")
+                    .attach(module)?;
+                bcc::Tracepoint::new()
+                    .handler("tracepoint__syscalls__sys_enter_execveat")
+                    .subsystem("syscalls")
+                    .tracepoint("sys_enter_execveat")
+                    .attach(module)?;
+                bcc::Tracepoint::new()
+                    .handler("tracepoint__syscalls__sys_exit_execveat")
+                    .subsystem("syscalls")
+                    .tracepoint("sys_exit_execveat")
+                    .attach(module)?;
+            }
+            AttachStrategy::SchedTracepoint => {
+                bcc::Tracepoint::new()
+                    .handler("tracepoint__sched__sched_process_exec")
+                    .subsystem("sched")
+                    .tracepoint("sched_process_exec")
+                    .attach(module)?;
+            }
+            AttachStrategy::Kprobe => {
+                // Resolves the kernel specific symbol name, e.g., __x64_sys_execve
+                let execve = module.get_syscall_fnname("execve");
+                bcc::Kprobe::new()
+                    .handler("syscall__hld_execve_entry")
+                    .function(&execve)
+                    .attach(module)?;
+                bcc::Kretprobe::new()
+                    .handler("hld_syscall_execve_return")
+                    .function(&execve)
+                    .attach(module)?;
+                let execveat = module.get_syscall_fnname("execveat");
+                bcc::Kprobe::new()
+                    .handler("syscall__hld_execveat_entry")
+                    .function(&execveat)
+                    .attach(module)?;
+                bcc::Kretprobe::new()
+                    .handler("hld_syscall_execve_return")
+                    .function(&execveat)
+                    .attach(module)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for AttachStrategy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for AttachStrategy {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        AttachStrategy::ALL
+            .iter()
+            .find(|x| x.name() == s.to_lowercase())
+            .copied()
+            .ok_or_else(|| Error::InvalidAttachStrategy { name: s.to_string() })
+    }
+}
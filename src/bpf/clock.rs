@@ -0,0 +1,67 @@
+// Copyright 2020 Lukas Pustina
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::bpf::kernel_version;
+
+/// Kernel clock used by the BPF program for timestamps.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Clock {
+    /// `bpf_ktime_get_boot_ns`; includes time spent in suspend; requires Linux 5.7
+    Boot,
+    /// `bpf_ktime_get_ns`; stops during suspend
+    Monotonic,
+}
+
+impl Clock {
+    pub fn detect() -> Clock {
+        if kernel_version().map(|v| v >= (5, 7)).unwrap_or(false) {
+            Clock::Boot
+        } else {
+            Clock::Monotonic
+        }
+    }
+
+    /// Preprocessor defines which select the corresponding clock in exec_logger.c
+    pub fn defines(&self) -> &'static str {
+        match self {
+            Clock::Boot => "#define KTIME_BOOT\n",
+            Clock::Monotonic => "",
+        }
+    }
+
+    fn clock_id(&self) -> libc::clockid_t {
+        match self {
+            Clock::Boot => libc::CLOCK_BOOTTIME,
+            Clock::Monotonic => libc::CLOCK_MONOTONIC,
+        }
+    }
+
+    /// Current time of this clock in ns, cf. `KTIME_NS` in exec_logger.c
+    pub fn now(&self) -> u64 {
+        now(self.clock_id())
+    }
+
+    /// Returns the offset in ns to add to a timestamp of this clock to get ns since the epoch.
+    pub fn realtime_offset(&self) -> u64 {
+        let clock = now(self.clock_id());
+        let realtime = now(libc::CLOCK_REALTIME);
+        realtime.saturating_sub(clock)
+    }
+}
+
+fn now(clock_id: libc::clockid_t) -> u64 {
+    let mut ts = libc::timespec { tv_sec: 0, tv_nsec: 0 };
+    unsafe { libc::clock_gettime(clock_id, &mut ts) };
+    ts.tv_sec as u64 * 1_000_000_000 + ts.tv_nsec as u64
+}
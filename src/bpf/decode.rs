@@ -0,0 +1,245 @@
+// Copyright 2020 Lukas Pustina
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Decodes samples of the BPF program field by field.
+//!
+//! Samples come from the kernel, but their layout depends on the defines the program has been compiled with. So the
+//! decoder checks lengths and enum values instead of casting the buffer.
+
+use super::{
+    Ancestor, DentryPath, Event, EventHeader, EventType, Exec, ExecVariant, Exit, MalformedEvent, MAX_ANCESTORS_LIMIT,
+};
+use crate::{Error, Result};
+use byteorder::{NativeEndian, ReadBytesExt};
+use std::io::Cursor;
+use std::mem;
+
+impl Event {
+    /// Decodes an event as submitted by the BPF program.
+    pub fn decode(bytes: &[u8]) -> Result<Event> {
+        let mut decoder = Decoder::new(bytes);
+        let r#type = decoder.event_type()?;
+        decoder.rewind();
+        match r#type {
+            EventType::EVENT_EXEC => decoder.exec().map(Event::Exec),
+            EventType::EVENT_EXIT => decoder.exit().map(Event::Exit),
+        }
+    }
+
+    /// Decodes a sample; samples that cannot be decoded become `Event::Malformed`.
+    pub fn from_sample(bytes: &[u8]) -> Event {
+        Event::decode(bytes).unwrap_or_else(|error| {
+            Event::Malformed(MalformedEvent {
+                size: bytes.len(),
+                error,
+            })
+        })
+    }
+}
+
+/// Reads the fields of the C structs in order, aligned like a C compiler does.
+struct Decoder<'a> {
+    cursor: Cursor<&'a [u8]>,
+}
+
+impl<'a> Decoder<'a> {
+    fn new(bytes: &'a [u8]) -> Decoder<'a> {
+        Decoder {
+            cursor: Cursor::new(bytes),
+        }
+    }
+
+    fn rewind(&mut self) {
+        self.cursor.set_position(0)
+    }
+
+    fn position(&self) -> usize {
+        self.cursor.position() as usize
+    }
+
+    fn remaining(&self) -> usize {
+        self.cursor.get_ref().len().saturating_sub(self.position())
+    }
+
+    fn align(&mut self, align: usize) {
+        let padding = (align - self.position() % align) % align;
+        self.cursor.set_position((self.position() + padding) as u64)
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        self.align(mem::align_of::<u32>());
+        self.cursor
+            .read_u32::<NativeEndian>()
+            .map_err(|_| truncated(self.position()))
+    }
+
+    fn i32(&mut self) -> Result<i32> {
+        self.align(mem::align_of::<i32>());
+        self.cursor
+            .read_i32::<NativeEndian>()
+            .map_err(|_| truncated(self.position()))
+    }
+
+    fn u64(&mut self) -> Result<u64> {
+        self.align(mem::align_of::<u64>());
+        self.cursor
+            .read_u64::<NativeEndian>()
+            .map_err(|_| truncated(self.position()))
+    }
+
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8]> {
+        if self.remaining() < len {
+            return Err(truncated(self.position()));
+        }
+        let start = self.position();
+        self.cursor.set_position((start + len) as u64);
+        Ok(&self.cursor.get_ref()[start..start + len])
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N]> {
+        let mut array = [0u8; N];
+        array.copy_from_slice(self.bytes(N)?);
+        Ok(array)
+    }
+
+    fn event_type(&mut self) -> Result<EventType> {
+        match self.u32()? {
+            0 => Ok(EventType::EVENT_EXEC),
+            1 => Ok(EventType::EVENT_EXIT),
+            x => Err(malformed(format!("invalid event type {}", x))),
+        }
+    }
+
+    fn exec_variant(&mut self) -> Result<ExecVariant> {
+        match self.u32()? {
+            0 => Ok(ExecVariant::VARIANT_UNKNOWN),
+            1 => Ok(ExecVariant::VARIANT_EXECVE),
+            2 => Ok(ExecVariant::VARIANT_EXECVEAT),
+            x => Err(malformed(format!("invalid exec variant {}", x))),
+        }
+    }
+
+    fn dentry_path(&mut self) -> Result<DentryPath> {
+        let mut names = [[0u8; 64]; 8];
+        for name in names.iter_mut() {
+            *name = self.array()?;
+        }
+        let incomplete = self.u32()?;
+
+        Ok(DentryPath { names, incomplete })
+    }
+
+    fn ancestor(&mut self) -> Result<Ancestor> {
+        let pid = self.u32()?;
+        let comm = self.array()?;
+
+        Ok(Ancestor { pid, comm })
+    }
+
+    /// Decodes `struct data_t`, cf. exec_logger.c
+    fn exec(&mut self) -> Result<Exec> {
+        self.event_type()?;
+        let pid = self.u32()?;
+        let tid = self.u32()?;
+        let ts = self.u64()?;
+        let ppid = self.u32()?;
+        let ancestor = self.u32()?;
+        let comm = self.array()?;
+        let tty = self.array()?;
+        let uid = self.u32()?;
+        let gid = self.u32()?;
+        let ret_val = self.i32()?;
+        let variant = self.exec_variant()?;
+        let dirfd = self.i32()?;
+        let flags = self.i32()?;
+        let dir = self.dentry_path()?;
+        let cwd = self.dentry_path()?;
+        let args_count = self.i32()?;
+        let args_size = self.u32()? as usize;
+        let ancestors_count = self.u32()?;
+        let ancestors_max = self.u32()?;
+        let envs_count = self.u32()?;
+        let envs_size = self.u32()? as usize;
+        let args_truncated = self.u32()? != 0;
+        let envs_truncated = self.u32()? != 0;
+        debug_assert_eq!(self.position(), mem::size_of::<EventHeader>());
+
+        if ancestors_max > MAX_ANCESTORS_LIMIT || ancestors_count > ancestors_max {
+            return Err(malformed(format!(
+                "invalid number of ancestors {} of {}",
+                ancestors_count, ancestors_max
+            )));
+        }
+        let ancestors_end = self.position() + ancestors_max as usize * mem::size_of::<Ancestor>();
+        let ancestors = (0..ancestors_count)
+            .map(|_| self.ancestor())
+            .collect::<Result<Vec<_>>>()?;
+        if self.remaining() < ancestors_end.saturating_sub(self.position()) {
+            return Err(truncated(ancestors_end));
+        }
+        self.cursor.set_position(ancestors_end as u64);
+        let args = self.bytes(args_size)?.to_vec();
+        let envs = self.bytes(envs_size)?.to_vec();
+
+        Ok(Exec {
+            ts,
+            pid,
+            tid,
+            ppid,
+            ancestor,
+            comm,
+            tty,
+            uid,
+            gid,
+            ret_val,
+            variant,
+            dirfd,
+            flags,
+            dir,
+            cwd,
+            args_count,
+            ancestors,
+            args,
+            args_truncated,
+            envs_count,
+            envs,
+            envs_truncated,
+        })
+    }
+
+    /// Decodes `struct exit_t`, cf. exec_logger.c
+    fn exit(&mut self) -> Result<Exit> {
+        Ok(Exit {
+            r#type: self.event_type()?,
+            pid: self.u32()?,
+            ts: self.u64()?,
+            start_ts: self.u64()?,
+            ancestor: self.u32()?,
+            exit_code: self.i32()?,
+            signal: self.i32()?,
+            comm: self.array()?,
+            utime: self.u64()?,
+            stime: self.u64()?,
+            maxrss: self.u64()?,
+        })
+    }
+}
+
+fn truncated(position: usize) -> Error {
+    malformed(format!("event ends before byte {}", position))
+}
+
+fn malformed(reason: String) -> Error {
+    Error::MalformedEvent { reason }
+}
@@ -0,0 +1,194 @@
+// Copyright 2020 Lukas Pustina
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Accounting of execs the BPF program parked in `execs` but never reported.
+//!
+//! The BPF program counts execs it cannot park, because `execs` is full, in the array `dropped_execs`. If the return
+//! probe misses an exec, e.g., because too many execs return at once, its entry is never removed. Hence, entries older
+//! than `STALE_EXEC_AGE` are removed here and reported as `Event::Incomplete`; only entries that cannot be decoded are
+//! counted as dropped.
+
+use crate::bpf::clock::Clock;
+use crate::bpf::{until_nul, DroppedExecs, Event, EventHeader, Exec, Filters};
+use crate::Result;
+use bcc::BPF;
+use log::debug;
+use std::mem;
+use std::time::{Duration, Instant};
+
+/// Age after which an exec still parked in `execs` is considered to have missed its return
+const STALE_EXEC_AGE: Duration = Duration::from_secs(60);
+/// Interval between two searches for stale execs
+const SWEEP_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Receives `Event::Dropped` and `Event::Incomplete`
+pub(crate) type DroppedHandler = Box<dyn FnMut(Event)>;
+
+pub(crate) struct DroppedExecsMonitor<'a> {
+    module: &'a BPF,
+    clock: Clock,
+    /// Added to timestamps of the BPF program to get ns since the epoch, cf. `Clock::realtime_offset`
+    realtime_offset: u64,
+    handler: DroppedHandler,
+    /// Total of `dropped_execs` per CPU at the last check
+    dropped: Vec<u64>,
+    last_sweep: Instant,
+}
+
+impl<'a> DroppedExecsMonitor<'a> {
+    pub fn new(module: &'a BPF, clock: Clock, handler: DroppedHandler) -> DroppedExecsMonitor<'a> {
+        DroppedExecsMonitor {
+            module,
+            clock,
+            realtime_offset: clock.realtime_offset(),
+            handler,
+            dropped: Vec::new(),
+            last_sweep: Instant::now(),
+        }
+    }
+
+    /// Reports the execs dropped since the last check; searches for stale execs every `SWEEP_INTERVAL` and reports
+    /// those passing `filters`.
+    pub fn check(&mut self, filters: &Filters) -> Result<()> {
+        let mut count = self.count_dropped();
+        if self.last_sweep.elapsed() >= SWEEP_INTERVAL {
+            count += self.sweep(filters)?;
+            self.last_sweep = Instant::now();
+        }
+        if count > 0 {
+            (self.handler)(Event::Dropped(DroppedExecs { count }));
+        }
+
+        Ok(())
+    }
+
+    fn count_dropped(&mut self) -> u64 {
+        let mut count = 0;
+        for entry in self.module.table("dropped_execs").iter() {
+            let (cpu, total) = match (to_u32(&entry.key), to_u64(&entry.value)) {
+                (Some(cpu), Some(total)) => (cpu as usize, total),
+                _ => continue,
+            };
+            if cpu >= self.dropped.len() {
+                self.dropped.resize(cpu + 1, 0);
+            }
+            count += total.saturating_sub(self.dropped[cpu]);
+            self.dropped[cpu] = total;
+        }
+        count
+    }
+
+    /// Removes execs started more than `STALE_EXEC_AGE` ago, reports them, and returns the number of those which could
+    /// not be decoded.
+    fn sweep(&mut self, filters: &Filters) -> Result<u64> {
+        let now = self.clock.now();
+        let stale_age = STALE_EXEC_AGE.as_nanos() as u64;
+        let ts_offset = mem::offset_of!(EventHeader, ts);
+        let mut table = self.module.table("execs");
+        let stale: Vec<_> = table
+            .iter()
+            .filter(|entry| {
+                let ts = entry.value.get(ts_offset..).and_then(to_u64);
+                ts.map(|ts| now.saturating_sub(ts) > stale_age).unwrap_or(false)
+            })
+            .collect();
+        let mut undecodable = 0;
+        for mut entry in stale {
+            table.delete(&mut entry.key)?;
+            match Event::decode(&entry.value) {
+                Ok(Event::Exec(mut exec)) => {
+                    debug!("Removed stale exec of pid {}", exec.pid);
+                    exec.ts = exec.ts.saturating_add(self.realtime_offset);
+                    set_comm(&mut exec);
+                    if filters.matches(&exec) {
+                        (self.handler)(Event::Incomplete(exec));
+                    }
+                }
+                _ => {
+                    debug!("Removed stale exec that could not be decoded");
+                    undecodable += 1;
+                }
+            }
+        }
+
+        Ok(undecodable)
+    }
+}
+
+/// Sets comm like the kernel does on a successful exec, i.e., to the basename of the filename, because the exec has
+/// not returned to `submit_exec`.
+fn set_comm(exec: &mut Exec) {
+    let filename = until_nul(&exec.args);
+    let basename = filename.rsplit(|&c| c == b'/').next().unwrap_or_default();
+    let len = basename.len().min(exec.comm.len() - 1);
+    exec.comm = [0; 16];
+    exec.comm[..len].copy_from_slice(&basename[..len]);
+}
+
+fn to_u32(bytes: &[u8]) -> Option<u32> {
+    let mut buf = [0u8; 4];
+    buf.copy_from_slice(bytes.get(..4)?);
+    Some(u32::from_ne_bytes(buf))
+}
+
+fn to_u64(bytes: &[u8]) -> Option<u64> {
+    let mut buf = [0u8; 8];
+    buf.copy_from_slice(bytes.get(..8)?);
+    Some(u64::from_ne_bytes(buf))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bpf::{DentryPath, ExecVariant};
+
+    #[test]
+    fn set_comm_like_the_kernel() {
+        let path = DentryPath {
+            names: [[0; 64]; 8],
+            incomplete: 0,
+        };
+        let mut exec = Exec {
+            ts: 0,
+            pid: 42,
+            tid: 42,
+            ppid: 1,
+            ancestor: 0,
+            comm: *b"bash\0\0\0\0\0\0\0\0\0\0\0\0",
+            tty: [0; 64],
+            uid: 0,
+            gid: 0,
+            ret_val: 0,
+            variant: ExecVariant::VARIANT_EXECVE,
+            dirfd: libc::AT_FDCWD,
+            flags: 0,
+            dir: path,
+            cwd: path,
+            args_count: 2,
+            ancestors: Vec::new(),
+            args: b"/usr/bin/a-rather-long-program\0a-rather-long-program\0".to_vec(),
+            args_truncated: false,
+            envs_count: 0,
+            envs: Vec::new(),
+            envs_truncated: false,
+        };
+
+        set_comm(&mut exec);
+        assert_eq!(&exec.comm, b"a-rather-long-p\0");
+
+        exec.args = b"ls\0".to_vec();
+        set_comm(&mut exec);
+        assert_eq!(&exec.comm, b"ls\0\0\0\0\0\0\0\0\0\0\0\0\0\0");
+    }
+}
@@ -6,138 +6,818 @@
 // 25-Aug-2020   Lukas Pustina   Extended information collected.
 
 #include <uapi/linux/ptrace.h>
+#include <linux/fcntl.h>
+#include <linux/fdtable.h>
 #include <linux/fs.h>
+#include <linux/fs_struct.h>
+#include <linux/mount.h>
 #include <linux/sched.h>
 #include <linux/sched/signal.h>
 #include <linux/tty.h>
 
-#define ARGSIZE  128
 #define TTYSIZE 64
+#define DNAME_LEN 64
+#define PATH_DEPTH 8
+#define MAX_ANCESTOR_NAMES 64
+#define ANCESTOR_EXACT 0
+#define MAX_UID_RANGES 8
+#define MAX_GIDS 64
+#define MAX_COMMS 64
+#define MAX_PATH_PREFIXES 64
+#define PATH_PREFIX_LEN 64
+#define COMM_ALLOW 1
+#define COMM_DENY 2
+#define ENVSIZE 256
+#define MAX_ENV_NAMES 128
+#define ENV_NAME_LEN 32
+#define ENV_EXACT 0xFFFFFFFF
+#define ENV_ALLOW 1
+#define ENV_REDACT 2
+// Arguments are read until ARGS_BUDGET bytes are used, so the last one may end MAX_ARG_LEN bytes later
+#define ARGS_BUF_SIZE (ARGS_BUDGET + MAX_ARG_LEN)
+// MAX_ENVS is 0 unless environment variables are captured
+#define ENVS_BUF_SIZE (ENVSIZE * MAX_ENVS)
+
+// Loops over parameters are unrolled unless the kernel supports bounded loops
+#ifdef BOUNDED_LOOPS
+#define LOOP _Pragma("clang loop unroll(disable)")
+#else
+#define LOOP _Pragma("unroll")
+#endif
+
+#ifdef KTIME_BOOT
+#define KTIME_NS() bpf_ktime_get_boot_ns()
+#else
+#define KTIME_NS() bpf_ktime_get_ns()
+#endif
 
 enum event_type {
-    EVENT_ARG,
-    EVENT_RET,
+    EVENT_EXEC,
+    EVENT_EXIT,
+};
+
+enum exec_variant {
+    VARIANT_UNKNOWN,
+    VARIANT_EXECVE,
+    VARIANT_EXECVEAT,
+};
+
+// Path components from the leaf up to the root of the process, which is named "/", each 0 terminated.
+struct dentry_path_t {
+    char names[PATH_DEPTH][DNAME_LEN];
+    u32 incomplete; // the root of the process has not been reached, so names lacks the components above
 };
 
+struct ancestor_t {
+    u32 pid;
+    char comm[TASK_COMM_LEN];
+};
+
+// Fixed size part first, so userspace can read the header and then the first args_size bytes of args. All events start
+// with their type. The header up to ancestors must be a multiple of 8 bytes, cf. EventHeader in mod.rs.
 struct data_t {
+    enum event_type type;
     u32 pid;  // PID as in the userspace term (i.e. task->tgid in kernel)
+    u32 tid;  // Thread which called exec; it takes over pid in de_thread, if it is not the thread group leader
+    u64 ts;   // Start of exec in ns since boot or, on old kernels, since an arbitrary point in time; cf. KTIME_NS
     u32 ppid; // Parent PID as in the userspace term (i.e task->real_parent->tgid in kernel)
-    int ancestor;
+    u32 ancestor; // id of the matched ancestor name, 0 if none
     char comm[TASK_COMM_LEN];
-    enum event_type type;
-    char argv[ARGSIZE];
     char tty[TTYSIZE];
     u32 uid;
     u32 gid;
     int ret_val;
+    enum exec_variant variant;
+    int dirfd;
+    int flags;
+    struct dentry_path_t dir; // path of dirfd for execveat
+    struct dentry_path_t cwd;
+    int args_count;
+    unsigned int args_size;
+    unsigned int ancestors_count;
+    unsigned int ancestors_max; // lets userspace find args without knowing MAX_ANCESTORS
+    unsigned int envs_count;    // number of recorded environment variables, cf. env_names
+    unsigned int envs_size;
+    unsigned int args_truncated; // more arguments than max_args, an argument cut off, or reading one failed
+    unsigned int envs_truncated; // more environment variables than MAX_ENVS, or one could not be read completely
+    struct ancestor_t ancestors[MAX_ANCESTORS]; // parents starting with the nearest one
+    // filename and argv followed by the recorded environment variables, each 0 terminated
+    char args[ARGS_BUF_SIZE + ENVS_BUF_SIZE];
 };
 
+#define DATA_HEADER_SIZE offsetof(struct data_t, args)
+#define DATA_SIZE(data) (DATA_HEADER_SIZE + data->args_size + data->envs_size)
+
+// Start of the program currently running in a process, cf. sched_process_exit
+struct exec_start_t {
+    u64 ts;
+    u32 ancestor;
+};
+
+struct exit_t {
+    enum event_type type;
+    u32 pid;
+    u64 ts;       // End of process; cf. KTIME_NS
+    u64 start_ts; // Start of exec, i.e. ts of the corresponding data_t
+    u32 ancestor;
+    int exit_code;
+    int signal;
+    char comm[TASK_COMM_LEN];
+    u64 utime;  // ns
+    u64 stime;  // ns
+    u64 maxrss; // pages
+};
+
+#ifdef TRANSPORT_RINGBUF
+BPF_RINGBUF_OUTPUT(events, RINGBUF_PAGES);
+// The ring buffer does not report lost samples to userspace, so they are counted here per CPU.
+BPF_ARRAY(lost_events, u64, MAX_CPUS);
+#else
 BPF_PERF_OUTPUT(events);
+#endif
+// data_t is too large for the BPF stack, so it is assembled in a per-CPU scratch buffer first ...
+BPF_PERCPU_ARRAY(scratch, struct data_t, 1);
+// ... and then parked per thread id until execve returns; entries are allocated on demand as data_t may be large. An LRU
+// hash would have to be preallocated, so userspace removes entries whose return has been missed, cf. DroppedExecs.
+BPF_F_TABLE("hash", u32, struct data_t, execs, 10240, BPF_F_NO_PREALLOC);
+// A thread other than the leader changes its thread id to pid in de_thread, so its id at entry is kept per task. Entries
+// of missed returns are removed by the next exec of the same task_struct, or evicted as the least recently used.
+BPF_TABLE("lru_hash", u64, u32, exec_tids, 10240);
+// Execs which could not be parked in execs, because it was full, are counted per CPU.
+BPF_ARRAY(dropped_execs, u64, MAX_CPUS);
+#ifdef TRACE_EXIT
+BPF_HASH(exec_starts, u32, struct exec_start_t);
+#endif
 
-static int __submit_arg(struct pt_regs *ctx, void *ptr, struct data_t *data)
+// Key of ancestor_names; prefixes are stored with their length, exact names with ANCESTOR_EXACT. name is 0 padded.
+struct ancestor_key_t {
+    u32 len;
+    char name[TASK_COMM_LEN];
+};
+// Ancestor names to look for, filled by userspace; the value is the id of the name.
+BPF_HASH(ancestor_names, struct ancestor_key_t, u32, MAX_ANCESTOR_NAMES);
+
+#if MAX_ENVS > 0
+// Key of env_names like ancestor_key_t; prefixes are stored with their length, exact names with ENV_EXACT.
+struct env_key_t {
+    u32 len;
+    char name[ENV_NAME_LEN];
+};
+// Names of environment variables to record, filled by userspace; the value is a combination of ENV_ALLOW and
+// ENV_REDACT.
+BPF_HASH(env_names, struct env_key_t, u8, MAX_ENV_NAMES);
+#endif
+
+struct uid_range_t {
+    u32 min;
+    u32 max;
+};
+
+// Filters which userspace may change at run time
+struct config_t {
+    u32 max_args; // at most MAX_ARGS
+    u32 only_ancestor;
+    u32 uid_ranges_count;
+    u32 filter_gids;  // only gids in gids pass
+    u32 filter_comms; // only comms allowed in comms pass
+    u32 filter_paths; // only filenames starting with a prefix in path_prefixes pass
+    struct uid_range_t uid_ranges[MAX_UID_RANGES];
+};
+BPF_ARRAY(config, struct config_t, 1);
+
+BPF_HASH(gids, u32, u8, MAX_GIDS);
+
+struct comm_key_t {
+    char comm[TASK_COMM_LEN];
+};
+// Values are COMM_ALLOW or COMM_DENY
+BPF_HASH(comms, struct comm_key_t, u8, MAX_COMMS);
+
+// prefixlen is in bits
+struct path_key_t {
+    u32 prefixlen;
+    char path[PATH_PREFIX_LEN];
+};
+BPF_LPM_TRIE(path_prefixes, struct path_key_t, u8, MAX_PATH_PREFIXES);
+
+static u32 config_max_args()
+{
+    int zero = 0;
+    struct config_t *cfg = config.lookup(&zero);
+
+    if (!cfg || cfg->max_args == 0 || cfg->max_args > MAX_ARGS)
+        return MAX_ARGS;
+    return cfg->max_args;
+}
+
+static void submit_event(void *ctx, void *event, u32 len)
+{
+#ifdef TRANSPORT_RINGBUF
+    if (events.ringbuf_output(event, len, 0) != 0) {
+        u32 cpu = bpf_get_smp_processor_id();
+        lost_events.increment(cpu);
+    }
+#else
+    events.perf_submit(ctx, event, len);
+#endif
+}
+
+static int __submit_arg(void *ptr, struct data_t *data)
 {
-    bpf_probe_read(data->argv, sizeof(data->argv), ptr);
-    events.perf_submit(ctx, data, sizeof(struct data_t));
+    int len;
+
+    if (data->args_size >= ARGS_BUDGET) {
+        data->args_truncated = 1;
+        return 0;
+    }
+    len = bpf_probe_read_str(&data->args[data->args_size], MAX_ARG_LEN, ptr);
+    if (len <= 0 || len > MAX_ARG_LEN) {
+        // The argument exists, but could not be read, e.g., because its page is not resident
+        data->args_truncated = 1;
+        return 0;
+    }
+    // The argument has been cut off, unless it is exactly MAX_ARG_LEN - 1 bytes long
+    if (len == MAX_ARG_LEN)
+        data->args_truncated = 1;
+    data->args_count++;
+    data->args_size += len;
     return 1;
 }
 
-static int submit_arg(struct pt_regs *ctx, void *ptr, struct data_t *data)
+static int submit_arg(void *ptr, struct data_t *data)
 {
     const char *argp = NULL;
-    bpf_probe_read(&argp, sizeof(argp), ptr);
+    if (bpf_probe_read(&argp, sizeof(argp), ptr) != 0) {
+        data->args_truncated = 1;
+        return 0;
+    }
     if (argp) {
-        return __submit_arg(ctx, (void *)(argp), data);
+        return __submit_arg((void *)(argp), data);
     }
     return 0;
 }
 
-int hld_syscall_execve_entry(struct pt_regs *ctx,
-    const char __user *filename,
-    const char __user *const __user *__argv,
-    const char __user *const __user *__envp)
+#if MAX_ENVS > 0
+// Returns the combination of the env_names values matching the name of the variable in var and sets eq to the position
+// of its "="; all prefixes are looked up, so a variable may be allowed by one name and redacted by another.
+static u8 match_env(const char *var, int *eq)
+{
+    struct env_key_t key = {};
+    char name[ENV_NAME_LEN + 1] = {};
+    u8 action = 0;
+    u8 *value;
+
+    *eq = -1;
+    bpf_probe_read(name, sizeof(name), var);
+    LOOP
+    for (int i = 0; i <= ENV_NAME_LEN; i++) {
+        // prefix of length i
+        key.len = i;
+        value = env_names.lookup(&key);
+        if (value)
+            action |= *value;
+        if (name[i] == 0)
+            break;
+        if (name[i] == '=') {
+            *eq = i;
+            key.len = ENV_EXACT;
+            value = env_names.lookup(&key);
+            if (value)
+                action |= *value;
+            break;
+        }
+        // longer names can only match prefixes
+        if (i == ENV_NAME_LEN)
+            break;
+        key.name[i] = name[i];
+    }
+
+    return action;
+}
+
+// Records the variable at envp if its name is in env_names; only the name is recorded for redacted ones. Returns the
+// length of the variable including the terminating 0 as read, or 0 if it could not be read.
+static int submit_env(const char *envp, struct data_t *data)
+{
+    unsigned int pos;
+    int len, eq;
+    u8 action;
+
+    pos = data->args_size + data->envs_size;
+    if (pos > ARGS_BUF_SIZE + ENVS_BUF_SIZE - ENVSIZE)
+        return 0;
+    len = bpf_probe_read_str(&data->args[pos], ENVSIZE, envp);
+    if (len <= 0 || len > ENVSIZE)
+        return 0;
+    action = match_env(&data->args[pos], &eq);
+    if (!(action & ENV_ALLOW) || eq < 0 || eq > ENV_NAME_LEN)
+        return len;
+    if (action & ENV_REDACT) {
+        // "NAME=" followed by 0; userspace replaces the value
+        if (bpf_probe_read_str(&data->args[pos], eq + 2, envp) <= 0)
+            return len;
+        data->envs_size += eq + 2;
+    } else {
+        data->envs_size += len;
+    }
+    data->envs_count++;
+    return len;
+}
+#endif
+
+static void read_dentry_path(struct dentry_path_t *path, struct task_struct *task, struct dentry *dentry,
+    struct vfsmount *mnt)
+{
+    struct dentry *root = task->fs->root.dentry;
+    struct vfsmount *root_mnt = task->fs->root.mnt;
+    struct dentry *parent;
+
+    path->incomplete = 1;
+
+    #pragma unroll
+    for (int i = 0; i < PATH_DEPTH; i++) {
+        path->names[i][0] = 0;
+    }
+
+    // Without a root within PATH_DEPTH components the path stays incomplete.
+    #pragma unroll
+    for (int i = 0; i < PATH_DEPTH; i++) {
+        if (dentry == root && mnt == root_mnt) {
+            path->names[i][0] = '/';
+            path->names[i][1] = 0;
+            path->incomplete = 0;
+            break;
+        }
+        // Mount points are not crossed, and a cut off name would be taken for another directory.
+        if (dentry == mnt->mnt_root || dentry->d_name.len >= DNAME_LEN)
+            break;
+        bpf_probe_read_str(path->names[i], DNAME_LEN, dentry->d_name.name);
+        parent = dentry->d_parent;
+        // Top of a disconnected dentry tree, which does not lead to mnt
+        if (parent == dentry)
+            break;
+        dentry = parent;
+    }
+}
+
+static void read_dirfd_path(struct dentry_path_t *path, struct task_struct *task, int dirfd)
 {
-    // create data here and pass to submit_arg to save stack space (#555)
-    struct data_t data = {};
+    struct file *file = NULL;
+    struct file **fds;
+    struct fdtable *fdt;
+
+    path->names[0][0] = 0;
+    path->incomplete = 0;
+    if (dirfd < 0)
+        return;
+
+    fdt = task->files->fdt;
+    if (dirfd >= fdt->max_fds)
+        return;
+    fds = fdt->fd;
+    bpf_probe_read(&file, sizeof(file), &fds[dirfd]);
+    if (!file)
+        return;
+
+    read_dentry_path(path, task, file->f_path.dentry, file->f_path.mnt);
+}
+
+static int exec_entry(enum exec_variant variant, int dirfd, const char __user *filename,
+    const char __user *const __user *__argv, const char __user *const __user *__envp, int flags)
+{
+    int zero = 0;
+    struct data_t *data;
     struct task_struct *task;
+    u64 pid_tgid;
+    u64 task_key;
+    u32 tid;
+    u32 max_args;
+    const char *argp = NULL;
+
+    data = scratch.lookup(&zero);
+    if (!data)
+        return 0;
 
-    data.pid = bpf_get_current_pid_tgid() >> 32;
+    data->ts = KTIME_NS();
+    pid_tgid = bpf_get_current_pid_tgid();
+    tid = pid_tgid;
+    data->pid = pid_tgid >> 32;
+    data->tid = tid;
 
     task = (struct task_struct *)bpf_get_current_task();
+    task_key = (u64)task;
+    // An entry left over by a missed return of an earlier exec of this task, or of a task since freed whose
+    // task_struct has been reused, must not be taken for this exec
+    exec_tids.delete(&task_key);
+    if (data->tid != data->pid)
+        exec_tids.update(&task_key, &tid);
     // Some kernels, like Ubuntu 4.13.0-generic, return 0
     // as the real_parent->tgid.
     // We use the get_ppid function as a fallback in those cases. (#1883)
-    data.ppid = task->real_parent->tgid;
+    data->ppid = task->real_parent->tgid;
 
-    bpf_get_current_comm(&data.comm, sizeof(data.comm));
-    data.type = EVENT_ARG;
+    data->type = EVENT_EXEC;
+    data->variant = variant;
+    data->dirfd = dirfd;
+    data->flags = flags;
+    // AT_FDCWD is negative and hence, leaves dir empty
+    read_dirfd_path(&data->dir, task, dirfd);
+    data->args_count = 0;
+    data->args_size = 0;
+    data->envs_count = 0;
+    data->envs_size = 0;
+    data->args_truncated = 0;
+    data->envs_truncated = 0;
+    // Fields set by submit_exec are reset, so userspace can decode an exec whose return has been missed, cf. dropped.rs
+    data->ret_val = 0;
+    data->ancestor = 0;
+    data->ancestors_count = 0;
+    data->ancestors_max = MAX_ANCESTORS;
+    data->comm[0] = 0;
+    data->tty[0] = 0;
+    data->uid = task->cred->uid.val;
+    data->gid = task->cred->gid.val;
+    data->cwd.names[0][0] = 0;
+    data->cwd.incomplete = 0;
 
-    __submit_arg(ctx, (void *)filename, &data);
+    __submit_arg((void *)filename, data);
 
-    // skip first arg, as we submitted filename
-    #pragma unroll
-    for (int i = 1; i < MAX_ARGS; i++) {
-        if (submit_arg(ctx, (void *)&__argv[i], &data) == 0)
+    max_args = config_max_args();
+    LOOP
+    for (int i = 0; i < MAX_ARGS; i++) {
+        if (i >= max_args)
+            break;
+        if (submit_arg((void *)&__argv[i], data) == 0)
              goto out;
     }
 
-    // handle truncated argument list
-    char ellipsis[] = "...";
-    __submit_arg(ctx, (void *)ellipsis, &data);
+    // all max_args arguments have been read, so the list is truncated if there is another one
+    if (bpf_probe_read(&argp, sizeof(argp), (void *)&__argv[max_args]) != 0 || argp != NULL)
+        data->args_truncated = 1;
 out:
+#if MAX_ENVS > 0
+    LOOP
+    for (int i = 0; i < MAX_ENVS; i++) {
+        argp = NULL;
+        bpf_probe_read(&argp, sizeof(argp), (void *)&__envp[i]);
+        if (!argp)
+            goto envs_out;
+        if (submit_env(argp, data) == 0) {
+            data->envs_truncated = 1;
+            goto envs_out;
+        }
+    }
+    argp = NULL;
+    bpf_probe_read(&argp, sizeof(argp), (void *)&__envp[MAX_ENVS]);
+    data->envs_truncated = argp != NULL;
+envs_out:
+#endif
+    if (execs.update(&tid, data) != 0) {
+        u32 cpu = bpf_get_smp_processor_id();
+        dropped_execs.increment(cpu);
+    }
     return 0;
 }
 
-int hld_syscall_execve_return(struct pt_regs *ctx)
+// Returns the id of the ancestor name matching comm or 0; shorter prefixes take precedence over longer ones and exact
+// names.
+static u32 match_ancestor(const char *comm)
+{
+    struct ancestor_key_t key = {};
+    u32 *id;
+
+    #pragma unroll
+    for (int i = 0; i < TASK_COMM_LEN - 1; i++) {
+        if (comm[i] == 0)
+            break;
+        key.name[i] = comm[i];
+        key.len = i + 1;
+        id = ancestor_names.lookup(&key);
+        if (id)
+            return *id;
+    }
+    key.len = ANCESTOR_EXACT;
+    id = ancestor_names.lookup(&key);
+
+    return id ? *id : 0;
+}
+
+// Returns whether the exec passes the filters of config.
+static int filter_exec(struct data_t *data)
+{
+    int zero = 0;
+    struct config_t *cfg;
+    struct comm_key_t comm_key = {};
+    struct path_key_t path_key = {};
+    u8 *action;
+    int match;
+
+    cfg = config.lookup(&zero);
+    if (!cfg)
+        return 1;
+
+    if (cfg->only_ancestor && !data->ancestor)
+        return 0;
+
+    if (cfg->uid_ranges_count) {
+        match = 0;
+        #pragma unroll
+        for (int i = 0; i < MAX_UID_RANGES; i++) {
+            if (i < cfg->uid_ranges_count && data->uid >= cfg->uid_ranges[i].min && data->uid <= cfg->uid_ranges[i].max)
+                match = 1;
+        }
+        if (!match)
+            return 0;
+    }
+
+    if (cfg->filter_gids && !gids.lookup(&data->gid))
+        return 0;
+
+    __builtin_memcpy(comm_key.comm, data->comm, TASK_COMM_LEN);
+    action = comms.lookup(&comm_key);
+    if (action && *action == COMM_DENY)
+        return 0;
+    if (cfg->filter_comms && !(action && *action == COMM_ALLOW))
+        return 0;
+
+    if (cfg->filter_paths) {
+        path_key.prefixlen = PATH_PREFIX_LEN * 8;
+        // the filename is the first of args
+        if (data->args_size > 0)
+            bpf_probe_read_str(path_key.path, PATH_PREFIX_LEN, data->args);
+        if (!path_prefixes.lookup(&path_key))
+            return 0;
+    }
+
+    return 1;
+}
+
+static int submit_exec(void *ctx, struct data_t *data)
 {
-    struct data_t data = {};
     struct task_struct *task;
-    int ancestor = false;
+    u32 ancestor = 0;
     struct task_struct *parent_task;
-    char compare_buf[sizeof("ANCESTOR_NAME")];
-
-    data.pid = bpf_get_current_pid_tgid() >> 32;
+    unsigned int len;
+    u32 parent_pid;
 
     task = (struct task_struct *)bpf_get_current_task();
-    // Some kernels, like Ubuntu 4.13.0-generic, return 0
-    // as the real_parent->tgid.
-    // We use the get_ppid function as a fallback in those cases. (#1883)
-    data.ppid = task->real_parent->tgid;
 
-    // Try to find ancestor of this process
+    // Collect ancestors of this process and find the nearest one with an expected name
+    data->ancestors_count = 0;
+    data->ancestors_max = MAX_ANCESTORS;
     parent_task = task->real_parent;
-    #pragma unroll
-    for (int i = 0; i < MAX_ANCESTORS - 1; i++) {
-        bpf_probe_read(&compare_buf, sizeof(compare_buf), parent_task->comm);
-        // No access to libc::strcmp allowed and __builtin_memcmp doesn't seem to work on 18.04.
-        #pragma unroll
-        for (int j = 0; j < sizeof(compare_buf) - 1; j++) {
-            char left = "ANCESTOR_NAME"[j];
-            char right = compare_buf[j];
-            if (left == right) {
-                ancestor = true;
-            } else {
-                ancestor = false;
-                goto cmp_done;
-            }
-        }
-cmp_done:
-        if (ancestor) {
-            goto find_done;
-        }
+    LOOP
+    for (int i = 0; i < MAX_ANCESTORS; i++) {
+        parent_pid = parent_task->tgid;
+        // The idle task is the parent of init and kthreadd
+        if (parent_pid == 0)
+            break;
+        data->ancestors[i].pid = parent_pid;
+        bpf_probe_read(&data->ancestors[i].comm, TASK_COMM_LEN, parent_task->comm);
+        data->ancestors_count++;
+
+        if (!ancestor)
+            ancestor = match_ancestor(data->ancestors[i].comm);
         parent_task = parent_task->real_parent;
     }
-find_done:
-    data.ancestor = ancestor;
+    data->ancestor = ancestor;
+
+    bpf_probe_read_str(data->tty, TTYSIZE, task->signal->tty->name);
+
+    data->uid = task->cred->uid.val;
+    data->gid = task->cred->gid.val;
+
+    bpf_get_current_comm(&data->comm, sizeof(data->comm));
+
+    if (!filter_exec(data))
+        return 0;
+
+    read_dentry_path(&data->cwd, task, task->fs->pwd.dentry, task->fs->pwd.mnt);
+
+#ifdef TRACE_EXIT
+    if (data->ret_val == 0) {
+        struct exec_start_t start = {};
+        start.ts = data->ts;
+        start.ancestor = ancestor;
+        exec_starts.update(&data->pid, &start);
+    }
+#endif
+
+    len = DATA_SIZE(data);
+    if (len <= sizeof(*data))
+        submit_event(ctx, data, len);
+
+    return 0;
+}
+
+static int exec_return(void *ctx, int ret_val)
+{
+    struct data_t *data;
+    u64 task_key;
+    u32 *exec_tid;
+    u32 tid;
+
+    tid = bpf_get_current_pid_tgid();
+    // After a successful exec by a thread other than the leader, the current thread id is pid
+    task_key = (u64)bpf_get_current_task();
+    exec_tid = exec_tids.lookup(&task_key);
+    if (exec_tid) {
+        tid = *exec_tid;
+        exec_tids.delete(&task_key);
+    }
+    data = execs.lookup(&tid);
+    if (!data)
+        return 0;
 
-    bpf_probe_read_str(data.tty, TTYSIZE, task->signal->tty->name);
+    data->ret_val = ret_val;
+    submit_exec(ctx, data);
+    execs.delete(&tid);
 
-    data.uid = task->cred->uid.val;
-    data.gid = task->cred->gid.val;
+    return 0;
+}
+
+#ifdef ATTACH_SYSCALL_TRACEPOINTS
+TRACEPOINT_PROBE(syscalls, sys_enter_execve)
+{
+    return exec_entry(VARIANT_EXECVE, AT_FDCWD, args->filename, args->argv, args->envp, 0);
+}
 
-    bpf_get_current_comm(&data.comm, sizeof(data.comm));
-    data.type = EVENT_RET;
-    data.ret_val = PT_REGS_RC(ctx);
-    events.perf_submit(ctx, &data, sizeof(data));
+TRACEPOINT_PROBE(syscalls, sys_exit_execve)
+{
+    return exec_return(args, args->ret);
+}
+
+TRACEPOINT_PROBE(syscalls, sys_enter_execveat)
+{
+    return exec_entry(VARIANT_EXECVEAT, args->fd, args->filename, args->argv, args->envp, args->flags);
+}
+
+TRACEPOINT_PROBE(syscalls, sys_exit_execveat)
+{
+    return exec_return(args, args->ret);
+}
+#endif
+
+#ifdef ATTACH_SCHED_TRACEPOINT
+// Fires only after a successful exec, in the context of the new program. Hence, there is no return value and the
+// arguments have to be read from the new program's memory.
+TRACEPOINT_PROBE(sched, sched_process_exec)
+{
+    int zero = 0;
+    struct data_t *data;
+    struct task_struct *task;
+    unsigned long arg_start, arg_end, size, max_size;
+    int len;
+#if MAX_ENVS > 0
+    unsigned long env, env_end;
+#endif
+
+    data = scratch.lookup(&zero);
+    if (!data)
+        return 0;
+
+    data->ts = KTIME_NS();
+    task = (struct task_struct *)bpf_get_current_task();
+    data->pid = bpf_get_current_pid_tgid() >> 32;
+    // The thread which called exec; de_thread has already given it the id pid
+    data->tid = args->old_pid;
+    data->ppid = task->real_parent->tgid;
+    data->type = EVENT_EXEC;
+    data->variant = VARIANT_UNKNOWN;
+    data->dirfd = AT_FDCWD;
+    data->flags = 0;
+    data->dir.names[0][0] = 0;
+    data->dir.incomplete = 0;
+    data->ret_val = 0;
+    data->args_count = 0;
+    data->args_size = 0;
+    data->envs_count = 0;
+    data->envs_size = 0;
+    data->args_truncated = 0;
+    data->envs_truncated = 0;
+
+    len = bpf_probe_read_str(data->args, MAX_ARG_LEN, (void *)args + (args->__data_loc_filename & 0xFFFF));
+    if (len <= 0 || len > MAX_ARG_LEN)
+        return 0;
+    data->args_count++;
+    data->args_size = len;
+
+    arg_start = task->mm->arg_start;
+    arg_end = task->mm->arg_end;
+    if (arg_start >= arg_end)
+        goto submit;
+
+    size = arg_end - arg_start;
+    // approximates the limit of arguments by their max size
+    max_size = MAX_ARG_LEN * config_max_args();
+    if (size > max_size)
+        size = max_size;
+    if (size > ARGS_BUDGET)
+        size = ARGS_BUDGET;
+    data->args_truncated = size < arg_end - arg_start;
+    if (data->args_size > MAX_ARG_LEN)
+        goto submit;
+    if (bpf_probe_read(&data->args[data->args_size], size, (void *)arg_start) != 0) {
+        data->args_truncated = 1;
+        goto submit;
+    }
+    data->args_size += size;
+
+submit:
+#if MAX_ENVS > 0
+    // The variables are stored one after another, so the next one starts after the 0 of the previous one. A variable
+    // filling ENVSIZE may have been cut off, so where the next one starts is unknown.
+    env = task->mm->env_start;
+    env_end = task->mm->env_end;
+    if (data->args_size > ARGS_BUF_SIZE)
+        goto envs_out;
+    LOOP
+    for (int i = 0; i < MAX_ENVS; i++) {
+        if (env >= env_end)
+            goto envs_out;
+        len = submit_env((const char *)env, data);
+        if (len <= 0 || len >= ENVSIZE) {
+            data->envs_truncated = 1;
+            goto envs_out;
+        }
+        env += len;
+    }
+    data->envs_truncated = env < env_end;
+envs_out:
+#endif
+    return submit_exec(args, data);
+}
+#endif
+
+#ifdef ATTACH_KPROBE
+// The 'syscall__' prefix makes bcc read the arguments correctly on kernels using syscall wrappers.
+int syscall__hld_execve_entry(struct pt_regs *ctx,
+    const char __user *filename,
+    const char __user *const __user *__argv,
+    const char __user *const __user *__envp)
+{
+    return exec_entry(VARIANT_EXECVE, AT_FDCWD, filename, __argv, __envp, 0);
+}
+
+int syscall__hld_execveat_entry(struct pt_regs *ctx,
+    int fd,
+    const char __user *filename,
+    const char __user *const __user *__argv,
+    const char __user *const __user *__envp,
+    int flags)
+{
+    return exec_entry(VARIANT_EXECVEAT, fd, filename, __argv, __envp, flags);
+}
+
+int hld_syscall_execve_return(struct pt_regs *ctx)
+{
+    return exec_return(ctx, PT_REGS_RC(ctx));
+}
+#endif
+
+#ifdef TRACE_EXIT
+// Reports only processes which have been started by a traced exec.
+TRACEPOINT_PROBE(sched, sched_process_exit)
+{
+    struct exit_t exit = {};
+    struct exec_start_t *start;
+    struct task_struct *task;
+    struct task_struct *leader;
+    u32 pid;
+
+    pid = bpf_get_current_pid_tgid() >> 32;
+    task = (struct task_struct *)bpf_get_current_task();
+    // The process ends with its last thread, which need not be the leader, e.g., if it called pthread_exit. do_exit
+    // decrements live before this tracepoint.
+    if (task->signal->live.counter != 0)
+        return 0;
+    start = exec_starts.lookup(&pid);
+    if (!start)
+        return 0;
+    exit.start_ts = start->ts;
+    exit.ancestor = start->ancestor;
+    // Threads exiting concurrently may all see live at 0, but only one of them removes the start
+    if (exec_starts.delete(&pid) != 0)
+        return 0;
+
+    leader = task->group_leader;
+    exit.type = EVENT_EXIT;
+    exit.pid = pid;
+    exit.ts = KTIME_NS();
+    exit.exit_code = (task->exit_code >> 8) & 0xff;
+    exit.signal = task->exit_code & 0x7f;
+    bpf_probe_read(&exit.comm, sizeof(exit.comm), leader->comm);
+    // Times of already released threads have been accumulated in signal; a leader which has exited before stays
+    // around as a zombie until the whole group is dead, so its times are not accumulated yet.
+    exit.utime = task->utime + task->signal->utime;
+    exit.stime = task->stime + task->signal->stime;
+    if (leader != task) {
+        exit.utime += leader->utime;
+        exit.stime += leader->stime;
+    }
+    exit.maxrss = task->signal->maxrss;
 
+    submit_event(args, &exit, sizeof(exit));
     return 0;
 }
+#endif
@@ -0,0 +1,715 @@
+// Copyright 2020 Lukas Pustina
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Filters live in BPF maps, so they can be changed while the BPF program keeps running.
+
+use crate::bpf::{until_nul, AncestorName, Exec, MatchMode, DEFAULT_ANCESTOR, MAX_ANCESTOR_NAMES};
+use crate::{Error, Result};
+use bcc::BPF;
+use std::collections::HashSet;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::{mpsc, Arc, Mutex, RwLock};
+
+/// Max number of uid ranges, cf. exec_logger.c
+pub const MAX_UID_RANGES: usize = 8;
+/// Max number of gids, cf. exec_logger.c
+pub const MAX_GIDS: usize = 64;
+/// Max number of allowed and denied comms, cf. exec_logger.c
+pub const MAX_COMMS: usize = 64;
+/// Max number of filename prefixes, cf. exec_logger.c
+pub const MAX_PATH_PREFIXES: usize = 64;
+/// Max length of a filename prefix; the BPF program compares this many bytes of a filename at most.
+pub const MAX_PATH_PREFIX_LEN: usize = 63;
+const TASK_COMM_LEN: usize = 16;
+const PATH_PREFIX_LEN: usize = 64;
+const COMM_ALLOW: u8 = 1;
+const COMM_DENY: u8 = 2;
+
+/// Filters of the BPF program which can be changed while running.
+///
+/// Execs which do not pass the filters are dropped inside the kernel. Parses from lines of
+///
+/// * `ancestor NAME[*][=LABEL]`
+/// * `only-ancestor`
+/// * `max-args NUMBER`
+/// * `uid UID` or `uid MIN-MAX`
+/// * `gid GID`
+/// * `comm-allow COMM` and `comm-deny COMM`
+/// * `path-prefix PREFIX`
+///
+/// Empty lines and lines starting with `#` are ignored.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Filters {
+    /// Ancestor names to look for; the nearest ancestor matching any of them is reported
+    pub ancestor_names: Vec<AncestorName>,
+    /// Reports only execs with an ancestor matching one of `ancestor_names`
+    pub only_ancestor: bool,
+    /// Max number of arguments to record; limited by and defaults to `KProbeOpts::max_args`
+    pub max_args: Option<u32>,
+    /// Reports only execs by uids in one of these ranges; all if empty
+    pub uid_ranges: Vec<UidRange>,
+    /// Reports only execs by these gids; all if empty
+    pub gids: Vec<u32>,
+    /// Reports only execs of programs with these comms; all if empty
+    pub comm_allow: Vec<String>,
+    /// Drops execs of programs with these comms
+    pub comm_deny: Vec<String>,
+    /// Reports only execs of filenames starting with one of these prefixes; all if empty. Relative filenames are
+    /// matched as passed to exec.
+    pub path_prefixes: Vec<String>,
+}
+
+impl Default for Filters {
+    fn default() -> Self {
+        Filters {
+            ancestor_names: vec![DEFAULT_ANCESTOR.parse().expect("valid default ancestor")],
+            only_ancestor: false,
+            max_args: None,
+            uid_ranges: Vec::new(),
+            gids: Vec::new(),
+            comm_allow: Vec::new(),
+            comm_deny: Vec::new(),
+            path_prefixes: Vec::new(),
+        }
+    }
+}
+
+/// Inclusive range of uids; parses from `UID` or `MIN-MAX`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UidRange {
+    pub min: u32,
+    pub max: u32,
+}
+
+impl fmt::Display for UidRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.min == self.max {
+            write!(f, "{}", self.min)
+        } else {
+            write!(f, "{}-{}", self.min, self.max)
+        }
+    }
+}
+
+impl FromStr for UidRange {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = || Error::InvalidUidRange { range: s.to_string() };
+        let (min, max) = match s.find('-') {
+            Some(pos) => (&s[..pos], &s[pos + 1..]),
+            None => (s, s),
+        };
+        let min = min.trim().parse().map_err(|_| invalid())?;
+        let max = max.trim().parse().map_err(|_| invalid())?;
+        if min > max {
+            return Err(invalid());
+        }
+
+        Ok(UidRange { min, max })
+    }
+}
+
+impl Filters {
+    /// Checks the filters against the limits of the BPF program compiled for `max_args` arguments.
+    pub fn validate(&self, max_args: u32) -> Result<()> {
+        if self.ancestor_names.len() > MAX_ANCESTOR_NAMES {
+            return Err(Error::TooManyAncestorNames {
+                max: MAX_ANCESTOR_NAMES,
+            });
+        }
+        let mut keys = HashSet::new();
+        for ancestor_name in &self.ancestor_names {
+            ancestor_name.validate()?;
+            if !keys.insert(ancestor_name.key()) {
+                return Err(Error::DuplicateAncestorName {
+                    name: ancestor_name.to_string(),
+                });
+            }
+        }
+        if let Some(value) = self.max_args {
+            if value == 0 || value > max_args {
+                return Err(Error::InvalidMaxArgs { value, max: max_args });
+            }
+        }
+
+        check_count("uid", self.uid_ranges.len(), MAX_UID_RANGES)?;
+        for range in &self.uid_ranges {
+            if range.min > range.max {
+                return Err(Error::InvalidUidRange {
+                    range: range.to_string(),
+                });
+            }
+        }
+        check_count("gid", self.gids.len(), MAX_GIDS)?;
+        check_count("comm", self.comm_allow.len() + self.comm_deny.len(), MAX_COMMS)?;
+        let mut comms = HashSet::new();
+        for comm in self.comm_allow.iter().chain(self.comm_deny.iter()) {
+            // The same comm cannot be allowed and denied at the same time
+            if comm.is_empty() || comm.len() > TASK_COMM_LEN - 1 || comm.contains('\0') || !comms.insert(comm) {
+                return Err(Error::InvalidComm { comm: comm.clone() });
+            }
+        }
+        check_count("path prefix", self.path_prefixes.len(), MAX_PATH_PREFIXES)?;
+        for prefix in &self.path_prefixes {
+            if prefix.is_empty() || prefix.len() > MAX_PATH_PREFIX_LEN || prefix.contains('\0') {
+                return Err(Error::InvalidPathPrefix { prefix: prefix.clone() });
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Entries of the BPF maps as pairs of key and value.
+    fn ancestor_name_entries(&self, ancestor_ids: &AncestorIds) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        self.ancestor_names
+            .iter()
+            .map(|x| Ok((x.key(), ancestor_ids.id(x)?.to_ne_bytes().to_vec())))
+            .collect()
+    }
+
+    fn gid_entries(&self) -> Vec<(Vec<u8>, Vec<u8>)> {
+        self.gids.iter().map(|x| (x.to_ne_bytes().to_vec(), vec![1])).collect()
+    }
+
+    fn comm_entries(&self) -> Vec<(Vec<u8>, Vec<u8>)> {
+        let allow = self
+            .comm_allow
+            .iter()
+            .map(|x| (padded(x, TASK_COMM_LEN), vec![COMM_ALLOW]));
+        let deny = self
+            .comm_deny
+            .iter()
+            .map(|x| (padded(x, TASK_COMM_LEN), vec![COMM_DENY]));
+        allow.chain(deny).collect()
+    }
+
+    fn path_prefix_entries(&self) -> Vec<(Vec<u8>, Vec<u8>)> {
+        self.path_prefixes
+            .iter()
+            .map(|x| {
+                let prefixlen = (x.len() as u32 * 8).to_ne_bytes();
+                let mut key = prefixlen.to_vec();
+                key.extend(padded(x, PATH_PREFIX_LEN));
+                (key, vec![1])
+            })
+            .collect()
+    }
+
+    /// Value of the BPF array `config`, cf. `struct config_t` in exec_logger.c
+    fn config(&self, max_args: u32) -> Vec<u8> {
+        let fields = [
+            self.max_args.unwrap_or(max_args).min(max_args),
+            self.only_ancestor as u32,
+            self.uid_ranges.len() as u32,
+            !self.gids.is_empty() as u32,
+            !self.comm_allow.is_empty() as u32,
+            !self.path_prefixes.is_empty() as u32,
+        ];
+        let mut config: Vec<u8> = fields.iter().flat_map(|x| x.to_ne_bytes().to_vec()).collect();
+        for i in 0..MAX_UID_RANGES {
+            let range = self.uid_ranges.get(i).copied().unwrap_or(UidRange { min: 0, max: 0 });
+            config.extend_from_slice(&range.min.to_ne_bytes());
+            config.extend_from_slice(&range.max.to_ne_bytes());
+        }
+        config
+    }
+
+    /// Finds the name matching the comm of an ancestor like `match_ancestor` in exec_logger.c, i.e., the shortest
+    /// prefix first and the exact name last.
+    pub(crate) fn match_ancestor(&self, comm: &[u8]) -> Option<&AncestorName> {
+        let comm = until_nul(comm);
+        let prefix = (1..=comm.len().min(TASK_COMM_LEN - 1)).find_map(|len| {
+            self.ancestor_names
+                .iter()
+                .find(|x| x.match_mode == MatchMode::Prefix && x.name.as_bytes() == &comm[..len])
+        });
+        prefix.or_else(|| {
+            self.ancestor_names
+                .iter()
+                .find(|x| x.match_mode == MatchMode::Exact && x.name.as_bytes() == comm)
+        })
+    }
+
+    /// Checks an exec against the filters like `filter_exec` in exec_logger.c; `exec.ancestor` has to be set.
+    pub(crate) fn matches(&self, exec: &Exec) -> bool {
+        if self.only_ancestor && exec.ancestor == 0 {
+            return false;
+        }
+        if !self.uid_ranges.is_empty() && !self.uid_ranges.iter().any(|x| x.min <= exec.uid && exec.uid <= x.max) {
+            return false;
+        }
+        if !self.gids.is_empty() && !self.gids.contains(&exec.gid) {
+            return false;
+        }
+        let comm = until_nul(&exec.comm);
+        if self.comm_deny.iter().any(|x| x.as_bytes() == comm) {
+            return false;
+        }
+        if !self.comm_allow.is_empty() && !self.comm_allow.iter().any(|x| x.as_bytes() == comm) {
+            return false;
+        }
+        if !self.path_prefixes.is_empty() {
+            let filename = until_nul(&exec.args);
+            let filename = &filename[..filename.len().min(MAX_PATH_PREFIX_LEN)];
+            if !self.path_prefixes.iter().any(|x| filename.starts_with(x.as_bytes())) {
+                return false;
+            }
+        }
+
+        true
+    }
+}
+
+fn check_count(filter: &'static str, count: usize, max: usize) -> Result<()> {
+    if count > max {
+        return Err(Error::TooManyFilterValues { filter, max });
+    }
+    Ok(())
+}
+
+fn padded(s: &str, len: usize) -> Vec<u8> {
+    let mut buf = vec![0u8; len];
+    let n = s.len().min(len);
+    buf[..n].copy_from_slice(&s.as_bytes()[..n]);
+    buf
+}
+
+impl FromStr for Filters {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut filters = Filters {
+            ancestor_names: Vec::new(),
+            ..Filters::default()
+        };
+
+        for (i, line) in s.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let invalid = || Error::InvalidFilter {
+                line: i + 1,
+                content: line.to_string(),
+            };
+            let mut parts = line.splitn(2, char::is_whitespace);
+            let directive = parts.next().unwrap_or_default();
+            let value = parts.next().map(str::trim).unwrap_or_default();
+            match (directive, value) {
+                ("only-ancestor", "") => filters.only_ancestor = true,
+                (_, "") => return Err(invalid()),
+                ("ancestor", _) => filters.ancestor_names.push(value.parse()?),
+                ("max-args", _) => filters.max_args = Some(value.parse().map_err(|_| invalid())?),
+                ("uid", _) => filters.uid_ranges.push(value.parse()?),
+                ("gid", _) => filters.gids.push(value.parse().map_err(|_| invalid())?),
+                ("comm-allow", _) => filters.comm_allow.push(value.to_string()),
+                ("comm-deny", _) => filters.comm_deny.push(value.to_string()),
+                ("path-prefix", _) => filters.path_prefixes.push(value.to_string()),
+                _ => return Err(invalid()),
+            }
+        }
+
+        Ok(filters)
+    }
+}
+
+/// Ids of ancestor names as reported by the BPF program.
+///
+/// Ids are never reused, so events submitted before a filter update still resolve to the right name.
+#[derive(Debug, Clone, Default)]
+pub struct AncestorIds {
+    names: Arc<RwLock<Vec<AncestorName>>>,
+}
+
+impl AncestorIds {
+    pub fn resolve(&self, id: u32) -> Option<AncestorName> {
+        let names = self.names.read().ok()?;
+        (id as usize).checked_sub(1).and_then(|i| names.get(i)).cloned()
+    }
+
+    pub(crate) fn id(&self, ancestor_name: &AncestorName) -> Result<u32> {
+        let mut names = self.names.write().map_err(|_| Error::RunTimeError {
+            msg: "failed to assign ancestor name id",
+        })?;
+        let pos = match names.iter().position(|x| x == ancestor_name) {
+            Some(pos) => pos,
+            None => {
+                names.push(ancestor_name.clone());
+                names.len() - 1
+            }
+        };
+
+        Ok(pos as u32 + 1)
+    }
+}
+
+/// Updates the filters of a running BPF program, cf. `RunningExecLogger::filter_updater`
+#[derive(Debug, Clone)]
+pub struct FilterUpdater {
+    filters: Arc<Mutex<Filters>>,
+    max_args: u32,
+    sender: mpsc::Sender<Filters>,
+}
+
+impl FilterUpdater {
+    /// Returns the current filters.
+    pub fn filters(&self) -> Result<Filters> {
+        let filters = self.filters.lock().map_err(|_| Error::RunTimeError {
+            msg: "failed to read filters",
+        })?;
+        Ok(filters.clone())
+    }
+
+    /// Replaces all filters.
+    pub fn set(&self, filters: Filters) -> Result<()> {
+        self.update(|x| *x = filters)
+    }
+
+    /// Adds an ancestor name or replaces one with the same name and match mode.
+    pub fn add_ancestor_name(&self, ancestor_name: AncestorName) -> Result<()> {
+        self.update(|x| {
+            x.ancestor_names.retain(|y| y.key() != ancestor_name.key());
+            x.ancestor_names.push(ancestor_name);
+        })
+    }
+
+    /// Removes all ancestor names with this name.
+    pub fn remove_ancestor_name(&self, name: &str) -> Result<()> {
+        self.update(|x| x.ancestor_names.retain(|y| y.name != name))
+    }
+
+    pub fn set_max_args(&self, max_args: Option<u32>) -> Result<()> {
+        self.update(|x| x.max_args = max_args)
+    }
+
+    fn update<F: FnOnce(&mut Filters)>(&self, f: F) -> Result<()> {
+        let mut filters = self.filters.lock().map_err(|_| Error::RunTimeError {
+            msg: "failed to update filters",
+        })?;
+        let mut updated = filters.clone();
+        f(&mut updated);
+        updated.validate(self.max_args)?;
+        self.sender.send(updated.clone()).map_err(|_| Error::RunTimeError {
+            msg: "failed to update filters, because logging thread has stopped",
+        })?;
+        *filters = updated;
+
+        Ok(())
+    }
+}
+
+/// Creates a channel to send filter updates to the BPF program compiled for `max_args` arguments.
+pub fn filter_channel(filters: Filters, max_args: u32) -> (FilterUpdater, mpsc::Receiver<Filters>) {
+    let (sender, receiver) = mpsc::channel();
+    let updater = FilterUpdater {
+        filters: Arc::new(Mutex::new(filters)),
+        max_args,
+        sender,
+    };
+
+    (updater, receiver)
+}
+
+/// Writes filters to the maps of the BPF program; entries of `previous` filters not present anymore are removed.
+pub(crate) fn apply_filters(
+    module: &BPF,
+    previous: Option<&Filters>,
+    filters: &Filters,
+    max_args: u32,
+    ancestor_ids: &AncestorIds,
+) -> Result<()> {
+    let empty = Filters {
+        ancestor_names: Vec::new(),
+        ..Filters::default()
+    };
+    let previous = previous.unwrap_or(&empty);
+
+    // New entries are added before and stale ones are removed after the config changes, so filters have no gap
+    let tables = [
+        (
+            "ancestor_names",
+            previous.ancestor_name_entries(ancestor_ids)?,
+            filters.ancestor_name_entries(ancestor_ids)?,
+        ),
+        ("gids", previous.gid_entries(), filters.gid_entries()),
+        ("comms", previous.comm_entries(), filters.comm_entries()),
+        (
+            "path_prefixes",
+            previous.path_prefix_entries(),
+            filters.path_prefix_entries(),
+        ),
+    ];
+    for (name, _, entries) in &tables {
+        let mut table = module.table(name);
+        for (key, value) in entries {
+            table.set(&mut key.clone(), &mut value.clone())?;
+        }
+    }
+
+    let mut config = module.table("config");
+    let mut key = 0u32.to_ne_bytes();
+    config.set(&mut key, &mut filters.config(max_args))?;
+
+    for (name, previous_entries, entries) in &tables {
+        let mut table = module.table(name);
+        let keys: HashSet<_> = entries.iter().map(|(key, _)| key).collect();
+        for (key, _) in previous_entries {
+            if !keys.contains(key) {
+                table.delete(&mut key.clone())?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bpf::{Ancestor, DentryPath, ExecVariant};
+
+    fn filters(s: &str) -> Filters {
+        s.parse().unwrap()
+    }
+
+    fn comm(s: &str) -> [u8; TASK_COMM_LEN] {
+        let mut buf = [0u8; TASK_COMM_LEN];
+        buf[..s.len()].copy_from_slice(s.as_bytes());
+        buf
+    }
+
+    fn exec(comm_: &str, uid: u32, gid: u32, filename: &str, ancestor: u32) -> Exec {
+        let path = DentryPath {
+            names: [[0; 64]; 8],
+            incomplete: 0,
+        };
+        Exec {
+            ts: 0,
+            pid: 42,
+            tid: 42,
+            ppid: 1,
+            ancestor,
+            comm: comm(comm_),
+            tty: [0; 64],
+            uid,
+            gid,
+            ret_val: 0,
+            variant: ExecVariant::VARIANT_EXECVE,
+            dirfd: libc::AT_FDCWD,
+            flags: 0,
+            dir: path,
+            cwd: path,
+            args_count: 0,
+            ancestors: vec![Ancestor {
+                pid: 1,
+                comm: comm("init"),
+            }],
+            args: format!("{}\0", filename).into_bytes(),
+            args_truncated: false,
+            envs_count: 0,
+            envs: Vec::new(),
+            envs_truncated: false,
+        }
+    }
+
+    #[test]
+    fn parse_filters() {
+        let filters = filters(
+            "# comment\n\
+             ancestor sshd=ssh\n\
+             ancestor cron*\n\
+             \n\
+             only-ancestor\n\
+             max-args 5\n\
+             uid 1000-1999\n\
+             uid 0\n\
+             gid 27\n\
+             comm-allow bash\n\
+             comm-deny ls\n\
+             path-prefix /usr/",
+        );
+
+        assert_eq!(
+            filters.ancestor_names,
+            vec![
+                AncestorName::new("sshd").label("ssh"),
+                AncestorName::new("cron").match_mode(MatchMode::Prefix),
+            ]
+        );
+        assert!(filters.only_ancestor);
+        assert_eq!(filters.max_args, Some(5));
+        assert_eq!(
+            filters.uid_ranges,
+            vec![UidRange { min: 1000, max: 1999 }, UidRange { min: 0, max: 0 }]
+        );
+        assert_eq!(filters.gids, vec![27]);
+        assert_eq!(filters.comm_allow, vec!["bash"]);
+        assert_eq!(filters.comm_deny, vec!["ls"]);
+        assert_eq!(filters.path_prefixes, vec!["/usr/"]);
+    }
+
+    #[test]
+    fn parse_empty_filters_has_no_ancestor_names() {
+        assert!(filters("").ancestor_names.is_empty());
+    }
+
+    #[test]
+    fn parse_invalid_filter_lines() {
+        for (s, line) in [
+            ("unknown 1", 1),
+            ("ancestor", 1),
+            ("# comment\nmax-args many", 2),
+            ("gid -1", 1),
+            ("only-ancestor yes", 1),
+            ("comm-deny", 1),
+        ] {
+            match s.parse::<Filters>() {
+                Err(Error::InvalidFilter { line: l, .. }) => assert_eq!(l, line, "{}", s),
+                x => panic!("expected invalid filter for '{}', got {:?}", s, x),
+            }
+        }
+        assert!(matches!(
+            "uid 10-1".parse::<Filters>(),
+            Err(Error::InvalidUidRange { .. })
+        ));
+        assert!(matches!(
+            "ancestor a=b=c\nancestor *".parse::<Filters>(),
+            Err(Error::InvalidAncestorName { .. })
+        ));
+    }
+
+    #[test]
+    fn parse_uid_range() {
+        assert_eq!("7".parse::<UidRange>().unwrap(), UidRange { min: 7, max: 7 });
+        assert_eq!(" 1 - 2 ".parse::<UidRange>().unwrap(), UidRange { min: 1, max: 2 });
+        for s in ["", "-", "1-", "-1", "a", "2-1", "1-2-3"] {
+            assert!(
+                matches!(s.parse::<UidRange>(), Err(Error::InvalidUidRange { .. })),
+                "{}",
+                s
+            );
+        }
+        assert_eq!(UidRange { min: 1, max: 2 }.to_string(), "1-2");
+        assert_eq!(UidRange { min: 1, max: 1 }.to_string(), "1");
+    }
+
+    #[test]
+    fn validate_limits() {
+        assert!(Filters::default().validate(20).is_ok());
+        assert!(matches!(
+            filters("max-args 21").validate(20),
+            Err(Error::InvalidMaxArgs { .. })
+        ));
+        assert!(matches!(
+            filters("max-args 0").validate(20),
+            Err(Error::InvalidMaxArgs { .. })
+        ));
+        let too_many_gids = (0..=MAX_GIDS).map(|x| format!("gid {}\n", x)).collect::<String>();
+        assert!(matches!(
+            filters(&too_many_gids).validate(20),
+            Err(Error::TooManyFilterValues { filter: "gid", .. })
+        ));
+        assert!(matches!(
+            filters("comm-allow a-very-long-command").validate(20),
+            Err(Error::InvalidComm { .. })
+        ));
+        let long_prefix = format!("path-prefix /{}", "x".repeat(MAX_PATH_PREFIX_LEN));
+        assert!(matches!(
+            filters(&long_prefix).validate(20),
+            Err(Error::InvalidPathPrefix { .. })
+        ));
+    }
+
+    #[test]
+    fn validate_rejects_comm_allowed_and_denied() {
+        assert!(matches!(
+            filters("comm-allow ls\ncomm-deny ls").validate(20),
+            Err(Error::InvalidComm { comm }) if comm == "ls"
+        ));
+        assert!(matches!(
+            filters("comm-deny ls\ncomm-deny ls").validate(20),
+            Err(Error::InvalidComm { .. })
+        ));
+        assert!(filters("comm-allow ls\ncomm-deny cat").validate(20).is_ok());
+    }
+
+    #[test]
+    fn validate_rejects_duplicate_ancestor_names() {
+        // Labels are not part of the key
+        assert!(matches!(
+            filters("ancestor sshd=a\nancestor sshd=b").validate(20),
+            Err(Error::DuplicateAncestorName { .. })
+        ));
+        assert!(filters("ancestor sshd\nancestor sshd*").validate(20).is_ok());
+    }
+
+    #[test]
+    fn match_ancestor_prefers_shortest_prefix_over_exact() {
+        let filters = filters("ancestor sshd\nancestor ssh*\nancestor s*\nancestor cron");
+
+        assert_eq!(filters.match_ancestor(&comm("sshd")).unwrap().name, "s");
+        assert_eq!(filters.match_ancestor(&comm("cron")).unwrap().name, "cron");
+        assert!(filters.match_ancestor(&comm("crond")).is_none());
+        assert!(filters.match_ancestor(&comm("")).is_none());
+
+        let filters = self::filters("ancestor sshd\nancestor ssh*");
+        assert_eq!(filters.match_ancestor(&comm("sshd")).unwrap().name, "ssh");
+        assert_eq!(filters.match_ancestor(&comm("ssh")).unwrap().name, "ssh");
+        let filters = self::filters("ancestor sshd\nancestor sshd-session*");
+        assert_eq!(filters.match_ancestor(&comm("sshd")).unwrap().name, "sshd");
+    }
+
+    #[test]
+    fn default_ancestor_matches_sshd_and_sshd_session() {
+        let filters = Filters::default();
+
+        assert_eq!(filters.match_ancestor(&comm("sshd")).unwrap().name, "sshd");
+        assert_eq!(filters.match_ancestor(&comm("sshd-session")).unwrap().name, "sshd");
+        assert!(filters.match_ancestor(&comm("ssh")).is_none());
+    }
+
+    #[test]
+    fn matches_like_filter_exec() {
+        let all = Filters::default();
+        assert!(all.matches(&exec("ls", 1000, 1000, "/bin/ls", 0)));
+
+        let only_ancestor = filters("only-ancestor");
+        assert!(!only_ancestor.matches(&exec("ls", 1000, 1000, "/bin/ls", 0)));
+        assert!(only_ancestor.matches(&exec("ls", 1000, 1000, "/bin/ls", 1)));
+
+        let uids = filters("uid 0\nuid 1000-1999");
+        assert!(uids.matches(&exec("ls", 0, 0, "/bin/ls", 0)));
+        assert!(uids.matches(&exec("ls", 1999, 0, "/bin/ls", 0)));
+        assert!(!uids.matches(&exec("ls", 2000, 0, "/bin/ls", 0)));
+
+        let gids = filters("gid 27");
+        assert!(gids.matches(&exec("ls", 0, 27, "/bin/ls", 0)));
+        assert!(!gids.matches(&exec("ls", 0, 0, "/bin/ls", 0)));
+
+        let comms = filters("comm-allow ls\ncomm-allow cat\ncomm-deny sh");
+        assert!(comms.matches(&exec("cat", 0, 0, "/bin/cat", 0)));
+        assert!(!comms.matches(&exec("sh", 0, 0, "/bin/sh", 0)));
+        assert!(!comms.matches(&exec("bash", 0, 0, "/bin/bash", 0)));
+
+        let paths = filters("path-prefix /usr/");
+        assert!(paths.matches(&exec("ls", 0, 0, "/usr/bin/ls", 0)));
+        assert!(!paths.matches(&exec("ls", 0, 0, "/bin/ls", 0)));
+        // Only the first MAX_PATH_PREFIX_LEN bytes are compared
+        let long = format!("/{}", "x".repeat(MAX_PATH_PREFIX_LEN));
+        let paths = filters(&format!("path-prefix {}", &long[..MAX_PATH_PREFIX_LEN]));
+        assert!(paths.matches(&exec("ls", 0, 0, &long, 0)));
+    }
+}
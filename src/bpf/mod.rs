@@ -12,160 +12,534 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use crate::Result;
-use bcc::{
-    perf_event::{init_perf_map, PerfMap},
-    BPF,
-};
-use log::info;
-use log::trace;
+pub use ancestor::{AncestorName, MatchMode, DEFAULT_ANCESTOR, MAX_ANCESTOR_NAMES};
+pub use attach::AttachStrategy;
+pub use filter::{filter_channel, AncestorIds, FilterUpdater, Filters, UidRange};
+pub use transport::Transport;
+
+use crate::env::EnvOpts;
+use crate::{Error, Result};
+use bcc::BPF;
+use clock::Clock;
+use dropped::DroppedExecsMonitor;
+use log::{debug, info, trace};
+use perf_map::PerfMap;
+use ring_buf::RingBuf;
 use std::{
-    ptr,
+    ffi::{CStr, OsString},
+    mem,
+    os::unix::ffi::OsStringExt,
     sync::{
         atomic::{AtomicBool, Ordering},
-        Arc,
+        mpsc, Arc,
     },
 };
 
+mod ancestor;
+mod attach;
+mod clock;
+mod decode;
+mod dropped;
+mod filter;
+mod perf_map;
+mod ring_buf;
+mod transport;
+
 #[repr(C)]
 #[allow(non_camel_case_types, dead_code)]
 #[derive(Debug, PartialEq, Eq)]
 pub enum EventType {
-    EVENT_ARG,
-    EVENT_RET,
+    EVENT_EXEC,
+    EVENT_EXIT,
+}
+
+#[repr(C)]
+#[allow(non_camel_case_types, dead_code)]
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum ExecVariant {
+    VARIANT_UNKNOWN,
+    VARIANT_EXECVE,
+    VARIANT_EXECVEAT,
+}
+
+/// Path components from the leaf up to the root of the process, cf. `struct dentry_path_t` in exec_logger.c
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct DentryPath {
+    pub names: [[u8; 64]; 8], // [PATH_DEPTH][DNAME_LEN], cf. exec_logger.c
+    /// Set if the path could not be followed up to the root of the process: it crosses a mount point, has a component
+    /// longer than DNAME_LEN, or more than PATH_DEPTH components. `names` then holds the components below.
+    pub incomplete: libc::c_uint,
 }
 
+/// Parent of an exec'ed process, cf. `struct ancestor_t` in exec_logger.c
 #[repr(C)]
-pub struct Event {
+#[derive(Debug, Clone, Copy)]
+pub struct Ancestor {
     pub pid: libc::c_uint,
+    pub comm: [u8; 16],
+}
+
+/// Fixed size part of `struct data_t` up to `ancestors`, cf. exec_logger.c
+///
+/// Only its layout is used; events are read by `Event::decode`.
+#[repr(C)]
+#[allow(dead_code)]
+struct EventHeader {
+    r#type: EventType,
+    pid: libc::c_uint,
+    tid: libc::c_uint,
+    ts: u64,
+    ppid: libc::c_uint,
+    ancestor: libc::c_uint,
+    comm: [u8; 16], // TASK_COMM_LEN, cf. exec_logger.c
+    tty: [u8; 64],  // TTYSIZE, cf. exec_logger.c
+    uid: libc::c_uint,
+    gid: libc::c_uint,
+    ret_val: libc::c_int,
+    variant: ExecVariant,
+    dirfd: libc::c_int,
+    flags: libc::c_int,
+    dir: DentryPath,
+    cwd: DentryPath,
+    args_count: libc::c_int,
+    args_size: libc::c_uint,
+    ancestors_count: libc::c_uint,
+    ancestors_max: libc::c_uint,
+    envs_count: libc::c_uint,
+    envs_size: libc::c_uint,
+    args_truncated: libc::c_uint,
+    envs_truncated: libc::c_uint,
+}
+
+/// Event as submitted by the BPF program.
+// Events are moved directly into the handler, so the size difference does not matter.
+#[allow(clippy::large_enum_variant)]
+pub enum Event {
+    Exec(Exec),
+    Exit(Exit),
+    /// Sample that could not be decoded; it is dropped
+    Malformed(MalformedEvent),
+    /// Execs dropped before they returned, which could not be reported
+    Dropped(DroppedExecs),
+    /// Exec whose return has been missed; the return value, ancestors, tty, and cwd are unknown
+    Incomplete(Exec),
+}
+
+/// Sample that does not match the layout of the events of the BPF program.
+#[derive(Debug)]
+pub struct MalformedEvent {
+    /// Size of the sample in bytes
+    pub size: usize,
+    pub error: Error,
+}
+
+/// One complete exec as assembled by the BPF program.
+pub struct Exec {
+    /// Start of exec in ns since the epoch
+    pub ts: u64,
+    /// Process id, i.e., the thread group id
+    pub pid: libc::c_uint,
+    /// Thread which called exec; differs from `pid` if it has not been the thread group leader
+    pub tid: libc::c_uint,
     pub ppid: libc::c_uint,
+    /// Id of the matched ancestor name, cf. `AncestorIds`; 0 if none
     pub ancestor: libc::c_uint,
-    pub comm: [u8; 16], // TASK_COMM_LEN, cf. exec_logger.c
-    pub r#type: EventType,
-    pub argv: [u8; 128], // ARGSIZE, cf. exec_logger.c
-    pub tty: [u8; 64],   // TTYSIZE, cf. exec_logger.c
+    pub comm: [u8; 16],
+    pub tty: [u8; 64],
     pub uid: libc::c_uint,
     pub gid: libc::c_uint,
     pub ret_val: libc::c_int,
+    pub variant: ExecVariant,
+    pub dirfd: libc::c_int,
+    pub flags: libc::c_int,
+    /// Path of `dirfd` for execveat
+    pub dir: DentryPath,
+    /// Current working directory
+    pub cwd: DentryPath,
+    pub args_count: libc::c_int,
+    /// Parents starting with the nearest one
+    pub ancestors: Vec<Ancestor>,
+    /// Filename followed by the arguments starting with argv[0], each 0 terminated
+    pub args: Vec<u8>,
+    /// Set if there are more arguments than `max_args`, an argument has been cut off, or reading one failed
+    pub args_truncated: bool,
+    /// Number of recorded environment variables
+    pub envs_count: libc::c_uint,
+    /// Environment variables allowed by `EnvOpts` as `NAME=VALUE`, each 0 terminated; values of redacted ones are left
+    /// out and the last one may be cut off
+    pub envs: Vec<u8>,
+    /// Set if there are more environment variables than `EnvOpts::max_vars` or one could not be read completely
+    pub envs_truncated: bool,
 }
 
-impl From<&[u8]> for Event {
-    fn from(bytes: &[u8]) -> Self {
-        parse_struct(bytes)
-    }
+/// Exit of a process started by a traced exec, cf. `struct exit_t` in exec_logger.c
+#[repr(C)]
+pub struct Exit {
+    pub r#type: EventType,
+    pub pid: libc::c_uint,
+    /// End of process in ns since the epoch
+    pub ts: u64,
+    /// Start of exec in ns since the epoch; equals `Exec::ts` of the corresponding exec
+    pub start_ts: u64,
+    /// Id of the matched ancestor name, cf. `Exec::ancestor`
+    pub ancestor: libc::c_uint,
+    pub exit_code: libc::c_int,
+    pub signal: libc::c_int,
+    pub comm: [u8; 16],
+    /// User CPU time in ns
+    pub utime: u64,
+    /// System CPU time in ns
+    pub stime: u64,
+    /// Max resident set size in pages
+    pub maxrss: u64,
+}
+
+/// Number of execs the BPF program dropped before they returned, because its map of pending execs was full or their
+/// return has been missed and they could not be decoded, cf. `dropped`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DroppedExecs {
+    pub count: u64,
+}
+
+/// Number of events the kernel could not hand over to userspace, because the buffer was full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LostEvents {
+    pub cpu: u32,
+    pub count: u64,
 }
 
 #[allow(clippy::unused_unit)]
-pub struct KProbe<F: FnOnce(Event) -> () + Clone + std::marker::Send + 'static> {
+pub struct KProbe<F, L>
+where
+    F: FnOnce(Event) -> () + Clone + std::marker::Send + 'static,
+    L: FnOnce(LostEvents) -> () + Clone + std::marker::Send + 'static,
+{
     runnable: Arc<AtomicBool>,
     handler: F,
+    lost_handler: L,
     opts: KProbeOpts,
+    filter_updates: mpsc::Receiver<Filters>,
+    ancestor_ids: AncestorIds,
 }
 
 #[allow(clippy::unused_unit)]
-impl<F: FnOnce(Event) -> () + Clone + std::marker::Send + 'static> KProbe<F> {
-    pub fn new(runnable: Arc<AtomicBool>, handler: F, opts: KProbeOpts) -> Self {
+impl<F, L> KProbe<F, L>
+where
+    F: FnOnce(Event) -> () + Clone + std::marker::Send + 'static,
+    L: FnOnce(LostEvents) -> () + Clone + std::marker::Send + 'static,
+{
+    pub fn new(
+        runnable: Arc<AtomicBool>,
+        handler: F,
+        lost_handler: L,
+        opts: KProbeOpts,
+        filter_updates: mpsc::Receiver<Filters>,
+        ancestor_ids: AncestorIds,
+    ) -> Self {
         KProbe {
             runnable,
             handler,
+            lost_handler,
             opts,
+            filter_updates,
+            ancestor_ids,
         }
     }
 
     pub fn run(self) -> Result<()> {
         info!("Running Kprobe handler: {:?}", &self.opts);
-        let handler = create_handler(self.handler);
+        self.opts.validate()?;
+        let clock = Clock::detect();
+        let dropped_handler = self.handler.clone();
+        let handler = create_handler(self.handler, clock.realtime_offset());
+        let lost_handler = create_lost_handler(self.lost_handler);
         // It is important, to keep bpf in scope while running the event_loop. Otherwise it gets
         // dropped and we loose the connection to our kprobe
-        let bpf = load_bpf(&self.opts)?;
+        let (bpf, strategy, transport) = load_bpf(&self.opts, clock, &self.ancestor_ids)?;
+        info!("Attached BPF program via {}", strategy);
+        info!("Receiving events via {} buffer", transport);
 
         // create events table
         let table = bpf.table("events");
-        let perf_map = init_perf_map(table, handler)?;
+        let events = match transport {
+            Transport::RingBuffer => {
+                let lost_table = bpf.table("lost_events");
+                Events::RingBuf(RingBuf::new(table, lost_table, handler(), lost_handler())?)
+            }
+            Transport::PerfBuffer => Events::PerfMap(PerfMap::new(table, handler, lost_handler)?),
+        };
 
-        event_loop(self.runnable, perf_map, self.opts.interval_ms)
+        let filters = FilterUpdates {
+            module: &bpf,
+            receiver: self.filter_updates,
+            current: self.opts.filters.clone(),
+            max_args: self.opts.max_args,
+            ancestor_ids: self.ancestor_ids,
+        };
+        let dropped = DroppedExecsMonitor::new(&bpf, clock, Box::new(move |event| (dropped_handler.clone())(event)));
+        event_loop(self.runnable, events, filters, dropped, self.opts.interval_ms)
     }
 }
 
 #[derive(Debug)]
 pub struct KProbeOpts {
+    /// Max number of arguments the BPF program is compiled for
     pub max_args: u32,
-    pub ancestor_name: String,
+    /// Max length of a single argument in bytes including the terminating 0; longer ones are cut off
+    pub max_arg_len: u32,
+    /// Number of bytes of filename and arguments after which no further arguments are read
+    pub args_budget: u32,
+    /// Initial filters
+    pub filters: Filters,
     pub max_ancestors: u32,
+    /// Environment variables to record; the BPF program reads at most `EnvOpts::max_vars` variables if enabled
+    pub env: EnvOpts,
     pub interval_ms: u32,
+    /// Attach strategy to use; if `None`, all strategies are tried in order.
+    pub attach: Option<AttachStrategy>,
+    /// Transport to use; if `None`, the ring buffer is used if supported by the kernel.
+    pub transport: Option<Transport>,
+    /// Reports the exit of processes started by traced execs
+    pub trace_exit: bool,
 }
 
 impl Default for KProbeOpts {
     fn default() -> Self {
         KProbeOpts {
             max_args: 20,
-            ancestor_name: "sshd".to_string(),
+            max_arg_len: 1024,
+            args_budget: 8192,
+            filters: Filters::default(),
             max_ancestors: 20,
+            env: EnvOpts::default(),
             interval_ms: 200,
+            attach: None,
+            transport: None,
+            trace_exit: false,
         }
     }
 }
 
+/// Upper bound for `KProbeOpts::max_args`; the BPF program unrolls a loop per argument.
+pub const MAX_ARGS_LIMIT: u32 = 128;
+/// Upper bound for `KProbeOpts::max_args` on kernels supporting bounded loops, cf. `bounded_loops`
+pub const MAX_ARGS_LIMIT_BOUNDED_LOOPS: u32 = 1024;
+/// Max size of an event; the BPF program assembles events in a per-CPU array whose values are limited to 32 KiB.
+pub const MAX_EVENT_SIZE: usize = 32 * 1024;
+/// Length of an environment variable including the terminating 0, cf. exec_logger.c
+const ENVSIZE: usize = 256;
+/// Upper bound for `KProbeOpts::max_ancestors`; the BPF program unrolls a loop per ancestor.
+pub const MAX_ANCESTORS_LIMIT: u32 = 64;
+/// Upper bound for `EnvOpts::max_vars`; the BPF program unrolls a loop per environment variable.
+pub const MAX_ENVS_LIMIT: u32 = 128;
+
 impl KProbeOpts {
-    fn max_args_key(&self) -> &'static str {
-        "MAX_ARGS"
+    /// Checks the options against the limits of the BPF program.
+    pub fn validate(&self) -> Result<()> {
+        let max_args_limit = if bounded_loops() {
+            MAX_ARGS_LIMIT_BOUNDED_LOOPS
+        } else {
+            MAX_ARGS_LIMIT
+        };
+        if self.max_args == 0 || self.max_args > max_args_limit {
+            return Err(Error::InvalidMaxArgs {
+                value: self.max_args,
+                max: max_args_limit,
+            });
+        }
+        if self.max_arg_len < 2 || self.max_arg_len > self.args_budget {
+            return Err(Error::InvalidMaxArgLen {
+                value: self.max_arg_len,
+                max: self.args_budget,
+            });
+        }
+        if self.max_ancestors == 0 || self.max_ancestors > MAX_ANCESTORS_LIMIT {
+            return Err(Error::InvalidMaxAncestors {
+                value: self.max_ancestors,
+                max: MAX_ANCESTORS_LIMIT,
+            });
+        }
+        if self.env.kernel_max_envs() > MAX_ENVS_LIMIT {
+            return Err(Error::InvalidMaxEnvs {
+                value: self.env.kernel_max_envs(),
+                max: MAX_ENVS_LIMIT,
+            });
+        }
+        self.env.validate()?;
+        if self.event_size() > MAX_EVENT_SIZE {
+            return Err(Error::EventTooLarge {
+                size: self.event_size(),
+                max: MAX_EVENT_SIZE,
+            });
+        }
+        self.filters.validate(self.max_args)
     }
 
-    fn max_args_value(&self) -> String {
-        self.max_args.to_string()
+    /// Max size of an exec event, i.e., `struct data_t` in exec_logger.c
+    fn event_size(&self) -> usize {
+        mem::size_of::<EventHeader>()
+            + self.max_ancestors as usize * mem::size_of::<Ancestor>()
+            + self.args_budget as usize
+            + self.max_arg_len as usize
+            + self.env.kernel_max_envs() as usize * ENVSIZE
     }
 
-    fn max_ancestors_key(&self) -> &'static str {
-        "MAX_ANCESTORS"
+    /// Preprocessor defines for the parameters of exec_logger.c
+    fn defines(&self) -> String {
+        format!(
+            "#define MAX_ARGS {}\n#define MAX_ARG_LEN {}\n#define ARGS_BUDGET {}\n#define MAX_ANCESTORS {}\n#define MAX_ENVS {}\n{}",
+            self.max_args,
+            self.max_arg_len,
+            self.args_budget,
+            self.max_ancestors,
+            self.env.kernel_max_envs(),
+            if bounded_loops() { "#define BOUNDED_LOOPS\n" } else { "" }
+        )
     }
+}
 
-    fn max_ancestors_value(&self) -> String {
-        self.max_ancestors.to_string()
-    }
+fn load_bpf(opts: &KProbeOpts, clock: Clock, ancestor_ids: &AncestorIds) -> Result<(BPF, AttachStrategy, Transport)> {
+    let strategies = match opts.attach {
+        Some(strategy) => vec![strategy],
+        None => AttachStrategy::ALL.to_vec(),
+    };
+    let transports = match opts.transport {
+        Some(transport) => vec![transport],
+        None => Transport::ALL.iter().filter(|x| x.is_supported()).copied().collect(),
+    };
 
-    fn ancestor_name_key(&self) -> &'static str {
-        "ANCESTOR_NAME"
+    let mut last_err = Error::RunTimeError {
+        msg: "no attach strategy available",
+    };
+    for transport in transports {
+        for strategy in &strategies {
+            match load_bpf_with(opts, *strategy, transport, clock, ancestor_ids) {
+                Ok(module) => return Ok((module, *strategy, transport)),
+                Err(err) => {
+                    debug!(
+                        "Failed to attach BPF program via {} using {} buffer: {:?}",
+                        strategy, transport, err
+                    );
+                    last_err = err;
+                }
+            }
+        }
     }
 
-    fn ancestor_name_value(&self) -> &str {
-        self.ancestor_name.as_str()
-    }
+    Err(last_err)
 }
 
-fn load_bpf(opts: &KProbeOpts) -> Result<BPF> {
+fn load_bpf_with(
+    opts: &KProbeOpts,
+    strategy: AttachStrategy,
+    transport: Transport,
+    clock: Clock,
+    ancestor_ids: &AncestorIds,
+) -> Result<BPF> {
     // load and parameterize BPF
     let code = include_str!("exec_logger.c");
-    let code = code.replace(opts.max_args_key(), &opts.max_args_value());
-    let code = code.replace(opts.ancestor_name_key(), opts.ancestor_name_value());
-    let code = code.replace(opts.max_ancestors_key(), &opts.max_ancestors_value());
+    let trace_exit = if opts.trace_exit { "#define TRACE_EXIT\n" } else { "" };
+    let code = format!(
+        "{}#define {}\n{}{}{}{}",
+        opts.defines(),
+        strategy.define(),
+        transport.defines(),
+        clock.defines(),
+        trace_exit,
+        code
+    );
     // compile the above BPF code!
     let mut module = BPF::new(&code)?;
-    // load + attach kprobes!
-    bcc::Kprobe::new()
-        .handler("hld_syscall_execve_entry")
-        .function("sys_execve")
-        .attach(&mut module)?;
-    bcc::Kretprobe::new()
-        .handler("hld_syscall_execve_return")
-        .function("sys_execve")
-        .attach(&mut module)?;
+    filter::apply_filters(&module, None, &opts.filters, opts.max_args, ancestor_ids)?;
+    let env_names = opts.env.kernel_names();
+    if !env_names.is_empty() {
+        let mut table = module.table("env_names");
+        for (mut key, mut value) in env_names {
+            table.set(&mut key, &mut value)?;
+        }
+    }
+    // load + attach probes!
+    strategy.attach(&mut module)?;
+    if opts.trace_exit {
+        bcc::Tracepoint::new()
+            .handler("tracepoint__sched__sched_process_exit")
+            .subsystem("sched")
+            .tracepoint("sched_process_exit")
+            .attach(&mut module)?;
+    }
 
     Ok(module)
 }
 
-fn event_loop(runnable: Arc<AtomicBool>, mut perf_map: PerfMap, interval_ms: u32) -> Result<()> {
+enum Events {
+    RingBuf(RingBuf),
+    PerfMap(PerfMap),
+}
+
+impl Events {
+    fn poll(&mut self, timeout: i32) -> Result<()> {
+        match self {
+            Events::RingBuf(ring_buf) => ring_buf.poll(timeout)?,
+            Events::PerfMap(perf_map) => perf_map.poll(timeout)?,
+        }
+
+        Ok(())
+    }
+}
+
+struct FilterUpdates<'a> {
+    module: &'a BPF,
+    receiver: mpsc::Receiver<Filters>,
+    /// Filters currently written to the maps
+    current: Filters,
+    max_args: u32,
+    ancestor_ids: AncestorIds,
+}
+
+impl<'a> FilterUpdates<'a> {
+    /// Applies the latest pending update, if any.
+    fn apply(&mut self) -> Result<()> {
+        if let Some(filters) = self.receiver.try_iter().last() {
+            info!("Updating filters: {:?}", filters);
+            filter::apply_filters(
+                self.module,
+                Some(&self.current),
+                &filters,
+                self.max_args,
+                &self.ancestor_ids,
+            )?;
+            self.current = filters;
+        }
+
+        Ok(())
+    }
+}
+
+fn event_loop(
+    runnable: Arc<AtomicBool>,
+    mut events: Events,
+    mut filters: FilterUpdates,
+    mut dropped: DroppedExecsMonitor,
+    interval_ms: u32,
+) -> Result<()> {
     while runnable.load(Ordering::SeqCst) {
-        trace!("Event loop: polling perf map.");
-        perf_map.poll(interval_ms as i32);
+        trace!("Event loop: polling events.");
+        events.poll(interval_ms as i32)?;
+        dropped.check(&filters.current)?;
+        filters.apply()?;
     }
 
     Ok(())
 }
 
-type HandlerGenerator = Box<dyn Fn() -> Box<dyn FnMut(&[u8]) + Send>>;
+type SampleHandler = Box<dyn FnMut(&[u8]) + Send>;
+type HandlerGenerator = Box<dyn Fn() -> SampleHandler>;
+type LostHandler = Box<dyn FnMut(u32, u64) + Send>;
+type LostHandlerGenerator = Box<dyn Fn() -> LostHandler>;
 
 #[allow(clippy::unused_unit)]
-fn create_handler<F>(handler: F) -> HandlerGenerator
+fn create_handler<F>(handler: F, realtime_offset: u64) -> HandlerGenerator
 where
     F: FnOnce(Event) -> () + Clone + std::marker::Send + 'static,
 {
@@ -173,21 +547,105 @@ where
         let handler = handler.clone();
         Box::new(move |x| {
             let h = handler.clone();
-            let event = x.into();
+            let mut event = Event::from_sample(x);
+            match &mut event {
+                Event::Exec(exec) => exec.ts = exec.ts.saturating_add(realtime_offset),
+                Event::Exit(exit) => {
+                    exit.ts = exit.ts.saturating_add(realtime_offset);
+                    exit.start_ts = exit.start_ts.saturating_add(realtime_offset);
+                }
+                Event::Malformed(_) | Event::Dropped(_) | Event::Incomplete(_) => {}
+            }
             h(event)
         })
     })
 }
 
-pub fn parse_struct<T>(buf: &[u8]) -> T {
-    unsafe { ptr::read(buf.as_ptr() as *const T) }
+#[allow(clippy::unused_unit)]
+fn create_lost_handler<L>(handler: L) -> LostHandlerGenerator
+where
+    L: FnOnce(LostEvents) -> () + Clone + std::marker::Send + 'static,
+{
+    Box::new(move || {
+        let handler = handler.clone();
+        Box::new(move |cpu, count| {
+            let h = handler.clone();
+            h(LostEvents { cpu, count })
+        })
+    })
+}
+
+/// Checks if the verifier of the running kernel accepts bounded loops, so they need not be unrolled.
+fn bounded_loops() -> bool {
+    kernel_version().map(|v| v >= (5, 3)).unwrap_or(false)
+}
+
+/// Returns major and minor version of the running kernel.
+pub(crate) fn kernel_version() -> Option<(u32, u32)> {
+    let mut uts: libc::utsname = unsafe { mem::zeroed() };
+    if unsafe { libc::uname(&mut uts) } != 0 {
+        return None;
+    }
+    let release = unsafe { CStr::from_ptr(uts.release.as_ptr()) }.to_string_lossy();
+    parse_kernel_version(&release)
+}
+
+fn parse_kernel_version(release: &str) -> Option<(u32, u32)> {
+    let mut parts = release.split(|c: char| !c.is_ascii_digit());
+    let major = parts.next()?.parse().ok()?;
+    let minor = parts.next()?.parse().ok()?;
+
+    Some((major, minor))
+}
+
+/// Splits a buffer of 0 terminated strings as assembled by the BPF program; the bytes are kept as they are.
+pub fn parse_strings(buf: &[u8]) -> Vec<OsString> {
+    if buf.is_empty() {
+        return Vec::new();
+    }
+    let buf = buf.strip_suffix(&[0]).unwrap_or(buf);
+    buf.split(|&x| x == 0).map(parse_os_string).collect()
+}
+
+/// Joins the path components collected by `read_dentry_path`, cf. exec_logger.c
+///
+/// Paths that could not be followed up to the root of the process are prefixed by "...".
+pub fn parse_dentry_path(path: &DentryPath) -> Option<OsString> {
+    let mut names: Vec<&[u8]> = path
+        .names
+        .iter()
+        .map(|x| until_nul(x))
+        .take_while(|x| !x.is_empty())
+        .collect();
+    let absolute = match names.last() {
+        Some(root) => root == b"/",
+        None => return None,
+    };
+    if absolute {
+        names.pop();
+    }
+    let absolute = absolute && path.incomplete == 0;
+    names.reverse();
+
+    let mut path = if absolute { b"/".to_vec() } else { b".../".to_vec() };
+    path.extend(names.join(&b'/'));
+    Some(OsString::from_vec(path))
+}
+
+/// Reads a 0 terminated string without changing its bytes.
+pub fn parse_os_string(buf: &[u8]) -> OsString {
+    OsString::from_vec(until_nul(buf).to_vec())
 }
 
 pub fn parse_string(buf: &[u8]) -> String {
+    String::from_utf8_lossy(until_nul(buf)).to_string()
+}
+
+pub(crate) fn until_nul(buf: &[u8]) -> &[u8] {
     // Search has to start from the front, so we find the _first_ 0 in order to prevent
     // reading invalid memory
     match buf.iter().position(|&x| x == 0) {
-        Some(zero_pos) => String::from_utf8_lossy(&buf[0..zero_pos]).to_string(),
-        None => String::from_utf8_lossy(buf).to_string(),
+        Some(zero_pos) => &buf[0..zero_pos],
+        None => buf,
     }
 }
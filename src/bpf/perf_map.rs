@@ -0,0 +1,146 @@
+// Copyright 2020 Lukas Pustina
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Per-CPU perf buffers for `BPF_PERF_OUTPUT`.
+//!
+//! Mirrors `bcc::perf_event::init_perf_map`, but additionally registers a callback for lost samples which the bcc
+//! crate does not expose.
+
+use crate::bpf::{LostHandler, SampleHandler};
+use crate::{Error, Result};
+use bcc::table::Table;
+use bcc::BccError;
+use bcc_sys::bccapi::{bpf_open_perf_buffer, perf_reader, perf_reader_fd, perf_reader_free, perf_reader_poll};
+use std::any::Any;
+use std::os::raw::{c_int, c_void};
+
+const PERF_READER_PAGE_CNT: c_int = 64;
+
+struct Callbacks {
+    cpu: u32,
+    sample: SampleHandler,
+    lost: LostHandler,
+    /// First panic of a handler; handlers are not called anymore afterwards
+    panic: Option<String>,
+}
+
+impl Callbacks {
+    /// Calls `f` unless a handler has panicked before; a panic must not unwind into C code.
+    fn call<F: FnOnce(&mut Callbacks)>(&mut self, f: F) {
+        if self.panic.is_some() {
+            return;
+        }
+        if let Err(panic) = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| f(self))) {
+            self.panic = Some(panic_message(panic));
+        }
+    }
+}
+
+fn panic_message(panic: Box<dyn Any + Send>) -> String {
+    match panic.downcast::<String>() {
+        Ok(msg) => *msg,
+        Err(panic) => panic
+            .downcast_ref::<&str>()
+            .map(|x| x.to_string())
+            .unwrap_or_else(|| "unknown panic".to_string()),
+    }
+}
+
+pub struct PerfMap {
+    readers: Vec<*mut perf_reader>,
+    // Referenced by the readers as callback cookies, so these must outlive the readers and are boxed to keep their
+    // addresses stable.
+    #[allow(clippy::vec_box)]
+    _callbacks: Vec<Box<Callbacks>>,
+}
+
+// The readers are owned exclusively by this struct.
+unsafe impl Send for PerfMap {}
+
+impl PerfMap {
+    pub fn new<S, L>(mut table: Table, sample: S, lost: L) -> Result<PerfMap>
+    where
+        S: Fn() -> SampleHandler,
+        L: Fn() -> LostHandler,
+    {
+        let mut perf_map = PerfMap {
+            readers: Vec::new(),
+            _callbacks: Vec::new(),
+        };
+
+        for cpu in bcc::cpuonline::get()? {
+            let mut callbacks = Box::new(Callbacks {
+                cpu: cpu as u32,
+                sample: sample(),
+                lost: lost(),
+                panic: None,
+            });
+            let reader = unsafe {
+                bpf_open_perf_buffer(
+                    Some(sample_callback),
+                    Some(lost_callback),
+                    callbacks.as_mut() as *mut Callbacks as *mut c_void,
+                    -1, /* pid */
+                    cpu as c_int,
+                    PERF_READER_PAGE_CNT,
+                )
+            } as *mut perf_reader;
+            if reader.is_null() {
+                return Err(BccError::OpenPerfBuffer.into());
+            }
+            perf_map.readers.push(reader);
+            perf_map._callbacks.push(callbacks);
+
+            let fd = unsafe { perf_reader_fd(reader) };
+            let mut key = (cpu as u32).to_ne_bytes();
+            let mut leaf = (fd as u32).to_ne_bytes();
+            table
+                .set(&mut key, &mut leaf)
+                .map_err(|_| BccError::InitializePerfMap)?;
+        }
+
+        Ok(perf_map)
+    }
+
+    /// Waits up to `timeout` ms for samples and passes them to the handlers; fails if a handler has panicked.
+    pub fn poll(&mut self, timeout: i32) -> Result<()> {
+        unsafe {
+            perf_reader_poll(self.readers.len() as c_int, self.readers.as_mut_ptr(), timeout);
+        }
+        match self._callbacks.iter_mut().find_map(|x| x.panic.clone()) {
+            Some(msg) => Err(Error::HandlerPanicked { msg }),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for PerfMap {
+    fn drop(&mut self) {
+        for reader in self.readers.drain(..) {
+            unsafe { perf_reader_free(reader as *mut c_void) }
+        }
+    }
+}
+
+unsafe extern "C" fn sample_callback(cookie: *mut c_void, raw: *mut c_void, size: c_int) {
+    let callbacks = &mut *(cookie as *mut Callbacks);
+    let sample = std::slice::from_raw_parts(raw as *const u8, size as usize);
+    callbacks.call(|x| (x.sample)(sample));
+}
+
+unsafe extern "C" fn lost_callback(cookie: *mut c_void, lost: u64) {
+    let callbacks = &mut *(cookie as *mut Callbacks);
+    let cpu = callbacks.cpu;
+    callbacks.call(|x| (x.lost)(cpu, lost));
+}
@@ -0,0 +1,214 @@
+// Copyright 2020 Lukas Pustina
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Consumer for `BPF_RINGBUF_OUTPUT`, which is not supported by the bcc crate.
+//!
+//! Follows the protocol of libbpf's `ring_buffer__poll`: The consumer position lives in a writable page, the producer
+//! position and the data area in read-only pages. The data area is mapped twice in a row, so samples wrapping around
+//! the end can be read as one contiguous slice.
+//!
+//! In contrast to perf buffers, the kernel does not report lost samples to the consumer. Therefore, the BPF program
+//! counts failed submissions per CPU in the array `lost_events` which is checked after each poll.
+
+use crate::bpf::transport::RINGBUF_PAGES;
+use crate::bpf::{LostHandler, SampleHandler};
+use crate::Result;
+use bcc::table::Table;
+use std::os::unix::io::RawFd;
+use std::ptr;
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+
+const BPF_RINGBUF_BUSY_BIT: u32 = 1 << 31;
+const BPF_RINGBUF_DISCARD_BIT: u32 = 1 << 30;
+const BPF_RINGBUF_HDR_SZ: u64 = 8;
+
+pub struct RingBuf {
+    epoll_fd: RawFd,
+    consumer: *mut libc::c_void,
+    producer: *mut libc::c_void,
+    page_size: usize,
+    mask: u64,
+    handler: SampleHandler,
+    lost_table: Table,
+    lost: Vec<u64>,
+    lost_handler: LostHandler,
+}
+
+// The mapped pages are owned exclusively by this struct.
+unsafe impl Send for RingBuf {}
+
+impl RingBuf {
+    pub fn new(
+        mut table: Table,
+        lost_table: Table,
+        handler: SampleHandler,
+        lost_handler: LostHandler,
+    ) -> Result<RingBuf> {
+        let map_fd = table.fd();
+        let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as usize;
+        let size = RINGBUF_PAGES * page_size;
+
+        let consumer = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                page_size,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED,
+                map_fd,
+                0,
+            )
+        };
+        if consumer == libc::MAP_FAILED {
+            return Err(std::io::Error::last_os_error().into());
+        }
+
+        let producer = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                page_size + 2 * size,
+                libc::PROT_READ,
+                libc::MAP_SHARED,
+                map_fd,
+                page_size as libc::off_t,
+            )
+        };
+        if producer == libc::MAP_FAILED {
+            let err = std::io::Error::last_os_error();
+            unsafe { libc::munmap(consumer, page_size) };
+            return Err(err.into());
+        }
+
+        let ring_buf = RingBuf {
+            epoll_fd: -1,
+            consumer,
+            producer,
+            page_size,
+            mask: size as u64 - 1,
+            handler,
+            lost_table,
+            lost: Vec::new(),
+            lost_handler,
+        };
+        ring_buf.with_epoll(map_fd)
+    }
+
+    fn with_epoll(mut self, map_fd: RawFd) -> Result<RingBuf> {
+        self.epoll_fd = unsafe { libc::epoll_create1(libc::EPOLL_CLOEXEC) };
+        if self.epoll_fd < 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+
+        let mut event = libc::epoll_event {
+            events: libc::EPOLLIN as u32,
+            u64: 0,
+        };
+        if unsafe { libc::epoll_ctl(self.epoll_fd, libc::EPOLL_CTL_ADD, map_fd, &mut event) } < 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+
+        Ok(self)
+    }
+
+    /// Waits up to `timeout` ms for the kernel to signal new samples and consumes all available samples.
+    pub fn poll(&mut self, timeout: i32) -> Result<()> {
+        let mut event = libc::epoll_event { events: 0, u64: 0 };
+        let res = unsafe { libc::epoll_wait(self.epoll_fd, &mut event, 1, timeout) };
+        if res < 0 {
+            let err = std::io::Error::last_os_error();
+            if err.kind() == std::io::ErrorKind::Interrupted {
+                return Ok(());
+            }
+            return Err(err.into());
+        }
+        if res > 0 {
+            self.consume();
+        }
+        self.check_lost();
+
+        Ok(())
+    }
+
+    fn check_lost(&mut self) {
+        for entry in self.lost_table.iter() {
+            let (cpu, total) = match (to_u32(&entry.key), to_u64(&entry.value)) {
+                (Some(cpu), Some(total)) => (cpu, total),
+                _ => continue,
+            };
+            let idx = cpu as usize;
+            if idx >= self.lost.len() {
+                self.lost.resize(idx + 1, 0);
+            }
+            if total > self.lost[idx] {
+                (self.lost_handler)(cpu, total - self.lost[idx]);
+                self.lost[idx] = total;
+            }
+        }
+    }
+
+    fn consume(&mut self) {
+        let consumer_pos = unsafe { &*(self.consumer as *const AtomicU64) };
+        let producer_pos = unsafe { &*(self.producer as *const AtomicU64) };
+        let data = unsafe { (self.producer as *const u8).add(self.page_size) };
+
+        let mut cons_pos = consumer_pos.load(Ordering::Acquire);
+        loop {
+            let prod_pos = producer_pos.load(Ordering::Acquire);
+            if cons_pos >= prod_pos {
+                break;
+            }
+            while cons_pos < prod_pos {
+                let sample = unsafe { data.add((cons_pos & self.mask) as usize) };
+                let len = unsafe { &*(sample as *const AtomicU32) }.load(Ordering::Acquire);
+                if len & BPF_RINGBUF_BUSY_BIT != 0 {
+                    return;
+                }
+                let sample_len = len & !(BPF_RINGBUF_BUSY_BIT | BPF_RINGBUF_DISCARD_BIT);
+                cons_pos += (sample_len as u64 + BPF_RINGBUF_HDR_SZ + 7) & !7;
+
+                if len & BPF_RINGBUF_DISCARD_BIT == 0 {
+                    let sample = unsafe {
+                        std::slice::from_raw_parts(sample.add(BPF_RINGBUF_HDR_SZ as usize), sample_len as usize)
+                    };
+                    (self.handler)(sample);
+                }
+                consumer_pos.store(cons_pos, Ordering::Release);
+            }
+        }
+    }
+}
+
+impl Drop for RingBuf {
+    fn drop(&mut self) {
+        let size = (self.mask + 1) as usize;
+        unsafe {
+            libc::munmap(self.consumer, self.page_size);
+            libc::munmap(self.producer, self.page_size + 2 * size);
+            if self.epoll_fd >= 0 {
+                libc::close(self.epoll_fd);
+            }
+        }
+    }
+}
+
+fn to_u32(bytes: &[u8]) -> Option<u32> {
+    let mut buf = [0u8; 4];
+    buf.copy_from_slice(bytes.get(..4)?);
+    Some(u32::from_ne_bytes(buf))
+}
+
+fn to_u64(bytes: &[u8]) -> Option<u64> {
+    let mut buf = [0u8; 8];
+    buf.copy_from_slice(bytes.get(..8)?);
+    Some(u64::from_ne_bytes(buf))
+}
@@ -0,0 +1,90 @@
+// Copyright 2020 Lukas Pustina
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::bpf::kernel_version;
+use crate::{Error, Result};
+use std::fmt;
+use std::str::FromStr;
+
+/// Number of pages of the BPF ring buffer; must be a power of 2.
+pub(crate) const RINGBUF_PAGES: usize = 128;
+
+/// Defines how events are transferred from the BPF program to userspace.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transport {
+    /// Single BPF ring buffer shared by all CPUs; requires Linux 5.8
+    RingBuffer,
+    /// Per-CPU perf buffers
+    PerfBuffer,
+}
+
+impl Transport {
+    /// All transports in the order they are tried if none has been selected explicitly.
+    pub const ALL: [Transport; 2] = [Transport::RingBuffer, Transport::PerfBuffer];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Transport::RingBuffer => "ring",
+            Transport::PerfBuffer => "perf",
+        }
+    }
+
+    /// Preprocessor defines which select the corresponding output in exec_logger.c
+    pub(crate) fn defines(&self) -> String {
+        match self {
+            Transport::RingBuffer => format!(
+                "#define TRANSPORT_RINGBUF\n#define RINGBUF_PAGES {}\n#define MAX_CPUS {}\n",
+                RINGBUF_PAGES,
+                max_cpus()
+            ),
+            Transport::PerfBuffer => format!("#define TRANSPORT_PERFBUF\n#define MAX_CPUS {}\n", max_cpus()),
+        }
+    }
+
+    /// Checks if the running kernel supports this transport.
+    pub fn is_supported(&self) -> bool {
+        match self {
+            Transport::RingBuffer => kernel_version().map(|v| v >= (5, 8)).unwrap_or(false),
+            Transport::PerfBuffer => true,
+        }
+    }
+}
+
+impl fmt::Display for Transport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Transport {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Transport::ALL
+            .iter()
+            .find(|x| x.name() == s.to_lowercase())
+            .copied()
+            .ok_or_else(|| Error::InvalidTransport { name: s.to_string() })
+    }
+}
+
+/// Returns the number of possible CPUs.
+fn max_cpus() -> usize {
+    let cpus = unsafe { libc::sysconf(libc::_SC_NPROCESSORS_CONF) };
+    if cpus > 0 {
+        cpus as usize
+    } else {
+        1
+    }
+}
@@ -0,0 +1,447 @@
+// Copyright 2020 Lukas Pustina
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Capture files of recorded events.
+//!
+//! A capture file starts with `MAGIC` and the format version as little endian u16. It is followed by records of a tag
+//! byte, the time of recording in ns since the epoch, and the fields of the event. Numbers are little endian, so
+//! captures can be replayed on any host. Byte strings are prefixed by their length; only the used parts of fixed size
+//! buffers are written.
+
+use crate::bpf::{self, Ancestor, DentryPath, DroppedExecs, Event, EventType, Exec, ExecVariant, Exit, LostEvents};
+use crate::env::EnvOpts;
+use crate::source::ReplayItem;
+use crate::{Error, Result};
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime};
+
+/// First bytes of a capture file
+pub const MAGIC: &[u8; 8] = b"EXECLOG\0";
+/// Version of the capture file format written and read by this crate
+pub const VERSION: u16 = 1;
+
+const TAG_EXEC: u8 = 1;
+const TAG_EXIT: u8 = 2;
+const TAG_LOST: u8 = 3;
+const TAG_DROPPED: u8 = 4;
+const TAG_INCOMPLETE: u8 = 5;
+/// Max size of the arguments and environment variables of an exec
+const MAX_BUF_LEN: usize = bpf::MAX_EVENT_SIZE;
+
+/// Defines how fast recorded events are replayed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReplaySpeed {
+    /// With the same delays as recorded
+    Original,
+    /// As fast as possible
+    Fast,
+}
+
+impl ReplaySpeed {
+    pub const ALL: [ReplaySpeed; 2] = [ReplaySpeed::Original, ReplaySpeed::Fast];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ReplaySpeed::Original => "original",
+            ReplaySpeed::Fast => "fast",
+        }
+    }
+}
+
+impl fmt::Display for ReplaySpeed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for ReplaySpeed {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        ReplaySpeed::ALL
+            .iter()
+            .find(|x| x.name() == s)
+            .copied()
+            .ok_or_else(|| Error::InvalidReplaySpeed { name: s.to_string() })
+    }
+}
+
+/// Recorded event or lost events.
+pub struct CaptureRecord {
+    /// Time of recording
+    pub time: SystemTime,
+    pub item: ReplayItem,
+}
+
+/// Writes events to a capture file.
+///
+/// Only the environment variables selected by `env` are recorded, so a capture never contains more of the environment
+/// than the output would; nothing is recorded by default.
+pub struct CaptureWriter {
+    writer: Box<dyn Write + Send>,
+    env: EnvOpts,
+}
+
+impl CaptureWriter {
+    /// Starts a capture by writing the file header.
+    pub fn new<W: Write + Send + 'static>(mut writer: W) -> Result<CaptureWriter> {
+        writer.write_all(MAGIC)?;
+        writer.write_u16::<LittleEndian>(VERSION)?;
+
+        Ok(CaptureWriter {
+            writer: Box::new(writer),
+            env: EnvOpts::default(),
+        })
+    }
+
+    /// Sets the environment variables to record; `ExecLogger::record` uses `ExecLoggerOpts::env`
+    pub fn env(mut self, env: EnvOpts) -> Self {
+        self.env = env;
+        self
+    }
+
+    pub fn create<P: AsRef<Path>>(path: P) -> Result<CaptureWriter> {
+        CaptureWriter::new(BufWriter::new(File::create(path)?))
+    }
+
+    /// Records an event; malformed events are skipped.
+    pub fn write_event(&mut self, event: &Event) -> Result<()> {
+        match event {
+            Event::Exec(exec) => self.write_exec(TAG_EXEC, exec),
+            Event::Incomplete(exec) => self.write_exec(TAG_INCOMPLETE, exec),
+            Event::Exit(exit) => self.write_exit(exit),
+            Event::Dropped(dropped) => {
+                self.write_tag(TAG_DROPPED)?;
+                self.writer.write_u64::<LittleEndian>(dropped.count)?;
+                Ok(())
+            }
+            Event::Malformed(_) => Ok(()),
+        }
+    }
+
+    pub fn write_lost(&mut self, lost: &LostEvents) -> Result<()> {
+        self.write_tag(TAG_LOST)?;
+        let w = &mut self.writer;
+        w.write_u32::<LittleEndian>(lost.cpu)?;
+        w.write_u64::<LittleEndian>(lost.count)?;
+
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> Result<()> {
+        self.writer.flush().map_err(Into::into)
+    }
+
+    fn write_tag(&mut self, tag: u8) -> Result<()> {
+        let time = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos() as u64;
+        self.writer.write_u8(tag)?;
+        self.writer.write_u64::<LittleEndian>(time)?;
+
+        Ok(())
+    }
+
+    fn write_exec(&mut self, tag: u8, exec: &Exec) -> Result<()> {
+        self.write_tag(tag)?;
+        let w = &mut self.writer;
+        w.write_u64::<LittleEndian>(exec.ts)?;
+        w.write_u32::<LittleEndian>(exec.pid)?;
+        w.write_u32::<LittleEndian>(exec.tid)?;
+        w.write_u32::<LittleEndian>(exec.ppid)?;
+        w.write_u32::<LittleEndian>(exec.ancestor)?;
+        write_short_bytes(w, bpf::until_nul(&exec.comm))?;
+        write_short_bytes(w, bpf::until_nul(&exec.tty))?;
+        w.write_u32::<LittleEndian>(exec.uid)?;
+        w.write_u32::<LittleEndian>(exec.gid)?;
+        w.write_i32::<LittleEndian>(exec.ret_val)?;
+        w.write_u8(exec.variant as u8)?;
+        w.write_i32::<LittleEndian>(exec.dirfd)?;
+        w.write_i32::<LittleEndian>(exec.flags)?;
+        write_dentry_path(w, &exec.dir)?;
+        write_dentry_path(w, &exec.cwd)?;
+        w.write_i32::<LittleEndian>(exec.args_count)?;
+        w.write_u8(exec.ancestors.len().min(u8::MAX as usize) as u8)?;
+        for ancestor in exec.ancestors.iter().take(u8::MAX as usize) {
+            w.write_u32::<LittleEndian>(ancestor.pid)?;
+            write_short_bytes(w, bpf::until_nul(&ancestor.comm))?;
+        }
+        write_bytes(w, &exec.args)?;
+        w.write_u8(exec.args_truncated as u8)?;
+        let (envs_count, envs) = self.env.select(&exec.envs);
+        let w = &mut self.writer;
+        w.write_u32::<LittleEndian>(envs_count)?;
+        write_bytes(w, &envs)?;
+        w.write_u8(exec.envs_truncated as u8)?;
+
+        Ok(())
+    }
+
+    fn write_exit(&mut self, exit: &Exit) -> Result<()> {
+        self.write_tag(TAG_EXIT)?;
+        let w = &mut self.writer;
+        w.write_u32::<LittleEndian>(exit.pid)?;
+        w.write_u64::<LittleEndian>(exit.ts)?;
+        w.write_u64::<LittleEndian>(exit.start_ts)?;
+        w.write_u32::<LittleEndian>(exit.ancestor)?;
+        w.write_i32::<LittleEndian>(exit.exit_code)?;
+        w.write_i32::<LittleEndian>(exit.signal)?;
+        write_short_bytes(w, bpf::until_nul(&exit.comm))?;
+        w.write_u64::<LittleEndian>(exit.utime)?;
+        w.write_u64::<LittleEndian>(exit.stime)?;
+        w.write_u64::<LittleEndian>(exit.maxrss)?;
+
+        Ok(())
+    }
+}
+
+impl fmt::Debug for CaptureWriter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CaptureWriter").field("env", &self.env).finish()
+    }
+}
+
+fn write_short_bytes<W: Write + ?Sized>(w: &mut W, bytes: &[u8]) -> io::Result<()> {
+    let len = bytes.len().min(u8::MAX as usize);
+    w.write_u8(len as u8)?;
+    w.write_all(&bytes[..len])
+}
+
+fn write_bytes<W: Write + ?Sized>(w: &mut W, bytes: &[u8]) -> io::Result<()> {
+    w.write_u32::<LittleEndian>(bytes.len() as u32)?;
+    w.write_all(bytes)
+}
+
+fn write_dentry_path<W: Write + ?Sized>(w: &mut W, path: &DentryPath) -> io::Result<()> {
+    let names: Vec<&[u8]> = path
+        .names
+        .iter()
+        .map(|x| bpf::until_nul(x))
+        .take_while(|x| !x.is_empty())
+        .collect();
+    w.write_u8(names.len() as u8)?;
+    for name in names {
+        write_short_bytes(w, name)?;
+    }
+    w.write_u8((path.incomplete != 0) as u8)
+}
+
+/// Reads the records of a capture file.
+pub struct CaptureReader {
+    reader: Box<dyn Read + Send>,
+}
+
+impl CaptureReader {
+    /// Checks the file header of a capture.
+    pub fn new<R: Read + Send + 'static>(mut reader: R) -> Result<CaptureReader> {
+        let mut magic = [0u8; 8];
+        reader
+            .read_exact(&mut magic)
+            .map_err(|_| invalid("not a capture file"))?;
+        if &magic != MAGIC {
+            return Err(invalid("not a capture file"));
+        }
+        let version = reader
+            .read_u16::<LittleEndian>()
+            .map_err(|_| invalid("truncated header"))?;
+        if version != VERSION {
+            return Err(invalid(format!("unsupported version {}", version)));
+        }
+
+        Ok(CaptureReader {
+            reader: Box::new(reader),
+        })
+    }
+
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<CaptureReader> {
+        CaptureReader::new(BufReader::new(File::open(path)?))
+    }
+
+    /// Reads the next record; returns `None` at the end of the capture.
+    pub fn read_record(&mut self) -> Result<Option<CaptureRecord>> {
+        let tag = match self.reader.read_u8() {
+            Ok(tag) => tag,
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(err) => return Err(err.into()),
+        };
+        self.read_item(tag).map(Some).map_err(|err| match err {
+            Error::IoError { source } if source.kind() == io::ErrorKind::UnexpectedEof => invalid("truncated record"),
+            err => err,
+        })
+    }
+
+    fn read_item(&mut self, tag: u8) -> Result<CaptureRecord> {
+        let time = SystemTime::UNIX_EPOCH + Duration::from_nanos(self.reader.read_u64::<LittleEndian>()?);
+        let item = match tag {
+            TAG_EXEC => ReplayItem::Event(Event::Exec(self.read_exec()?)),
+            TAG_INCOMPLETE => ReplayItem::Event(Event::Incomplete(self.read_exec()?)),
+            TAG_EXIT => ReplayItem::Event(Event::Exit(self.read_exit()?)),
+            TAG_DROPPED => ReplayItem::Event(Event::Dropped(DroppedExecs {
+                count: self.reader.read_u64::<LittleEndian>()?,
+            })),
+            TAG_LOST => ReplayItem::Lost(LostEvents {
+                cpu: self.reader.read_u32::<LittleEndian>()?,
+                count: self.reader.read_u64::<LittleEndian>()?,
+            }),
+            _ => return Err(invalid(format!("unknown record {}", tag))),
+        };
+
+        Ok(CaptureRecord { time, item })
+    }
+
+    fn read_exec(&mut self) -> Result<Exec> {
+        let r = &mut self.reader;
+        let ts = r.read_u64::<LittleEndian>()?;
+        let pid = r.read_u32::<LittleEndian>()?;
+        let tid = r.read_u32::<LittleEndian>()?;
+        let ppid = r.read_u32::<LittleEndian>()?;
+        let ancestor = r.read_u32::<LittleEndian>()?;
+        let comm = read_array(r)?;
+        let tty = read_array(r)?;
+        let uid = r.read_u32::<LittleEndian>()?;
+        let gid = r.read_u32::<LittleEndian>()?;
+        let ret_val = r.read_i32::<LittleEndian>()?;
+        let variant = match r.read_u8()? {
+            0 => ExecVariant::VARIANT_UNKNOWN,
+            1 => ExecVariant::VARIANT_EXECVE,
+            2 => ExecVariant::VARIANT_EXECVEAT,
+            x => return Err(invalid(format!("invalid exec variant {}", x))),
+        };
+        let dirfd = r.read_i32::<LittleEndian>()?;
+        let flags = r.read_i32::<LittleEndian>()?;
+        let dir = read_dentry_path(r)?;
+        let cwd = read_dentry_path(r)?;
+        let args_count = r.read_i32::<LittleEndian>()?;
+        let ancestors = (0..r.read_u8()?)
+            .map(|_| {
+                Ok(Ancestor {
+                    pid: r.read_u32::<LittleEndian>()?,
+                    comm: read_array(r)?,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let args = read_bytes(r)?;
+        let args_truncated = r.read_u8()? != 0;
+        let envs_count = r.read_u32::<LittleEndian>()?;
+        let envs = read_bytes(r)?;
+        let envs_truncated = r.read_u8()? != 0;
+
+        Ok(Exec {
+            ts,
+            pid,
+            tid,
+            ppid,
+            ancestor,
+            comm,
+            tty,
+            uid,
+            gid,
+            ret_val,
+            variant,
+            dirfd,
+            flags,
+            dir,
+            cwd,
+            args_count,
+            ancestors,
+            args,
+            args_truncated,
+            envs_count,
+            envs,
+            envs_truncated,
+        })
+    }
+
+    fn read_exit(&mut self) -> Result<Exit> {
+        let r = &mut self.reader;
+        Ok(Exit {
+            r#type: EventType::EVENT_EXIT,
+            pid: r.read_u32::<LittleEndian>()?,
+            ts: r.read_u64::<LittleEndian>()?,
+            start_ts: r.read_u64::<LittleEndian>()?,
+            ancestor: r.read_u32::<LittleEndian>()?,
+            exit_code: r.read_i32::<LittleEndian>()?,
+            signal: r.read_i32::<LittleEndian>()?,
+            comm: read_array(r)?,
+            utime: r.read_u64::<LittleEndian>()?,
+            stime: r.read_u64::<LittleEndian>()?,
+            maxrss: r.read_u64::<LittleEndian>()?,
+        })
+    }
+}
+
+impl Iterator for CaptureReader {
+    type Item = Result<CaptureRecord>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_record().transpose()
+    }
+}
+
+impl fmt::Debug for CaptureReader {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CaptureReader").finish_non_exhaustive()
+    }
+}
+
+/// Reads a byte string into a buffer padded with 0s.
+fn read_array<R: Read + ?Sized, const N: usize>(r: &mut R) -> Result<[u8; N]> {
+    let len = r.read_u8()? as usize;
+    if len > N {
+        return Err(invalid(format!("string of {} bytes exceeds {} bytes", len, N)));
+    }
+    let mut array = [0u8; N];
+    r.read_exact(&mut array[..len])?;
+    Ok(array)
+}
+
+fn read_bytes<R: Read + ?Sized>(r: &mut R) -> Result<Vec<u8>> {
+    let len = r.read_u32::<LittleEndian>()? as usize;
+    if len > MAX_BUF_LEN {
+        return Err(invalid(format!(
+            "buffer of {} bytes exceeds {} bytes",
+            len, MAX_BUF_LEN
+        )));
+    }
+    let mut buf = vec![0u8; len];
+    r.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+fn read_dentry_path<R: Read + ?Sized>(r: &mut R) -> Result<DentryPath> {
+    let mut path = DentryPath {
+        names: [[0; 64]; 8],
+        incomplete: 0,
+    };
+    let count = r.read_u8()? as usize;
+    if count > path.names.len() {
+        return Err(invalid(format!("path of {} components", count)));
+    }
+    for name in path.names.iter_mut().take(count) {
+        *name = read_array(r)?;
+    }
+    path.incomplete = r.read_u8()? as libc::c_uint;
+
+    Ok(path)
+}
+
+fn invalid<T: Into<String>>(reason: T) -> Error {
+    Error::InvalidCapture { reason: reason.into() }
+}
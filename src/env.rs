@@ -0,0 +1,319 @@
+// Copyright 2020 Lukas Pustina
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The BPF program records only the environment variables matching the allowlist and leaves out the values of redacted
+//! ones, cf. `env_names` in exec_logger.c. The allowlist is applied in userspace again for replays and to truncate
+//! values.
+
+use crate::{bpf, Error, Result};
+use std::collections::{BTreeMap, HashMap};
+use std::ffi::OsString;
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+
+/// Replaces the values of redacted environment variables.
+pub const REDACTED: &str = "<redacted>";
+/// Max number of distinct names in `EnvOpts::allow` and `EnvOpts::redact`, cf. exec_logger.c
+pub const MAX_ENV_NAMES: usize = 128;
+/// Max length of a name in `EnvOpts::allow` and `EnvOpts::redact` without the trailing `*`, cf. exec_logger.c
+pub const MAX_ENV_NAME_LEN: usize = 32;
+const ENV_EXACT: u32 = u32::MAX;
+const ENV_ALLOW: u8 = 1;
+const ENV_REDACT: u8 = 2;
+
+/// Selects the environment variables to capture from execs.
+///
+/// Names with a trailing `*` match all variables starting with the name, e.g., `KUBERNETES_*`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EnvOpts {
+    /// Names of variables to capture; nothing is captured if empty
+    pub allow: Vec<String>,
+    /// Names of captured variables whose values are replaced by `REDACTED`
+    pub redact: Vec<String>,
+    /// Max length of a value in bytes; longer values are truncated and end with "..."
+    pub max_value_len: usize,
+    /// Max number of variables the kernel reads per exec; variables further down the environment are not captured
+    pub max_vars: u32,
+}
+
+impl Default for EnvOpts {
+    fn default() -> Self {
+        EnvOpts {
+            allow: Vec::new(),
+            redact: Vec::new(),
+            max_value_len: 128,
+            max_vars: 64,
+        }
+    }
+}
+
+impl EnvOpts {
+    pub fn new<T: Into<String>, I: IntoIterator<Item = T>>(allow: I) -> EnvOpts {
+        EnvOpts {
+            allow: allow.into_iter().map(Into::into).collect(),
+            ..EnvOpts::default()
+        }
+    }
+
+    pub fn redact<T: Into<String>, I: IntoIterator<Item = T>>(mut self, redact: I) -> EnvOpts {
+        self.redact = redact.into_iter().map(Into::into).collect();
+        self
+    }
+
+    pub fn max_value_len(mut self, max_value_len: usize) -> EnvOpts {
+        self.max_value_len = max_value_len;
+        self
+    }
+
+    pub fn max_vars(mut self, max_vars: u32) -> EnvOpts {
+        self.max_vars = max_vars;
+        self
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !self.allow.is_empty()
+    }
+
+    /// Checks that all names can be matched by the BPF program.
+    pub fn validate(&self) -> Result<()> {
+        for pattern in self.allow.iter().chain(self.redact.iter()) {
+            if pattern.strip_suffix('*').unwrap_or(pattern).len() > MAX_ENV_NAME_LEN {
+                return Err(Error::EnvNameTooLong {
+                    name: pattern.clone(),
+                    max: MAX_ENV_NAME_LEN,
+                });
+            }
+        }
+        if self.kernel_names().len() > MAX_ENV_NAMES {
+            return Err(Error::TooManyEnvNames { max: MAX_ENV_NAMES });
+        }
+
+        Ok(())
+    }
+
+    /// Entries of the BPF map `env_names`, cf. `struct env_key_t` in exec_logger.c; empty unless enabled
+    ///
+    /// The BPF program combines the values of all keys matching a name, so names may be both allowed and redacted by
+    /// different patterns.
+    pub(crate) fn kernel_names(&self) -> Vec<(Vec<u8>, Vec<u8>)> {
+        if !self.is_enabled() {
+            return Vec::new();
+        }
+        let mut names: HashMap<Vec<u8>, u8> = HashMap::new();
+        let patterns = self.allow.iter().map(|x| (x, ENV_ALLOW));
+        for (pattern, action) in patterns.chain(self.redact.iter().map(|x| (x, ENV_REDACT))) {
+            *names.entry(env_key(pattern)).or_default() |= action;
+        }
+        names.into_iter().map(|(key, action)| (key, vec![action])).collect()
+    }
+
+    /// Number of variables the BPF program has to read, cf. `bpf::KProbeOpts::env`
+    pub(crate) fn kernel_max_envs(&self) -> u32 {
+        if self.is_enabled() {
+            self.max_vars
+        } else {
+            0
+        }
+    }
+
+    /// Picks the allowed variables from the environment as recorded by the BPF program.
+    ///
+    /// Values are kept as they are; names are matched as UTF-8.
+    pub fn capture(&self, envs: &[u8]) -> BTreeMap<String, OsString> {
+        if !self.is_enabled() {
+            return BTreeMap::new();
+        }
+        bpf::parse_strings(envs)
+            .into_iter()
+            .filter_map(|var| {
+                let var = var.as_bytes();
+                let pos = var.iter().position(|&c| c == b'=')?;
+                let name = String::from_utf8_lossy(&var[..pos]);
+                if !self.allow.iter().any(|x| matches(x, &name)) {
+                    return None;
+                }
+                let value = if self.redact.iter().any(|x| matches(x, &name)) {
+                    OsString::from(REDACTED)
+                } else {
+                    truncate(&var[pos + 1..], self.max_value_len)
+                };
+                Some((name.to_string(), value))
+            })
+            .collect()
+    }
+
+    /// Picks the allowed variables like `capture` and encodes them like the BPF program, i.e., as "NAME=value\0".
+    pub(crate) fn select(&self, envs: &[u8]) -> (u32, Vec<u8>) {
+        let vars = self.capture(envs);
+        let mut selected = Vec::new();
+        for (name, value) in &vars {
+            selected.extend_from_slice(name.as_bytes());
+            selected.push(b'=');
+            selected.extend_from_slice(value.as_bytes());
+            selected.push(0);
+        }
+        (vars.len() as u32, selected)
+    }
+}
+
+/// Key of `env_names`; prefixes are stored with their length, exact names with `ENV_EXACT`. The name is 0 padded.
+fn env_key(pattern: &str) -> Vec<u8> {
+    let (name, len) = match pattern.strip_suffix('*') {
+        Some(prefix) => (prefix, prefix.len() as u32),
+        None => (pattern, ENV_EXACT),
+    };
+    // Length has been checked by validate
+    let mut key = len.to_ne_bytes().to_vec();
+    let bytes = name.as_bytes();
+    let n = bytes.len().min(MAX_ENV_NAME_LEN);
+    key.extend_from_slice(&bytes[..n]);
+    key.resize(4 + MAX_ENV_NAME_LEN, 0);
+    key
+}
+
+fn matches(pattern: &str, name: &str) -> bool {
+    match pattern.strip_suffix('*') {
+        Some(prefix) => name.starts_with(prefix),
+        None => pattern == name,
+    }
+}
+
+fn truncate(value: &[u8], max_len: usize) -> OsString {
+    if value.len() <= max_len {
+        return OsString::from_vec(value.to_vec());
+    }
+    let mut value = value[..max_len].to_vec();
+    value.extend_from_slice(b"...");
+    OsString::from_vec(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn captured(opts: &EnvOpts, envs: &[u8]) -> Vec<(String, String)> {
+        opts.capture(envs)
+            .into_iter()
+            .map(|(k, v)| (k, v.to_string_lossy().into_owned()))
+            .collect()
+    }
+
+    fn pairs(xs: &[(&str, &str)]) -> Vec<(String, String)> {
+        xs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+    }
+
+    #[test]
+    fn capture_matches_exact_names_and_prefixes() {
+        let opts = EnvOpts::new(vec!["HOME", "KUBERNETES_*"]);
+        let envs = b"HOME=/root\0HOMEDIR=/home\0KUBERNETES_PORT=443\0KUBERNETES=1\0PATH=/bin\0";
+
+        assert_eq!(
+            captured(&opts, envs),
+            pairs(&[("HOME", "/root"), ("KUBERNETES_PORT", "443")])
+        );
+        assert!(captured(&EnvOpts::default(), envs).is_empty());
+        // Everything but variables without "="
+        assert_eq!(
+            captured(&EnvOpts::new(vec!["*"]), b"A=1\0B\0C=\0"),
+            pairs(&[("A", "1"), ("C", "")])
+        );
+    }
+
+    #[test]
+    fn capture_redacts_values() {
+        let opts = EnvOpts::new(vec!["HOME", "AWS_*"]).redact(vec!["AWS_SECRET*", "HOME"]);
+        let envs = b"HOME=/root\0AWS_REGION=eu\0AWS_SECRET_KEY=hunter2\0";
+
+        assert_eq!(
+            captured(&opts, envs),
+            pairs(&[("AWS_REGION", "eu"), ("AWS_SECRET_KEY", REDACTED), ("HOME", REDACTED)])
+        );
+        // As recorded by the BPF program
+        assert_eq!(captured(&opts, b"HOME=\0"), pairs(&[("HOME", REDACTED)]));
+        // Redacting does not allow
+        assert!(captured(&EnvOpts::new(vec!["HOME"]).redact(vec!["PATH"]), b"PATH=/bin\0").is_empty());
+    }
+
+    #[test]
+    fn capture_truncates_values() {
+        let opts = EnvOpts::new(vec!["A", "B"]).max_value_len(3);
+
+        assert_eq!(
+            captured(&opts, b"A=abc\0B=abcd\0"),
+            pairs(&[("A", "abc"), ("B", "abc...")])
+        );
+        // Truncating again keeps the value, so recorded values replay the same
+        assert_eq!(captured(&opts, b"B=abc...\0"), pairs(&[("B", "abc...")]));
+    }
+
+    #[test]
+    fn select_encodes_like_the_bpf_program() {
+        let opts = EnvOpts::new(vec!["HOME", "TOKEN"]).redact(vec!["TOKEN"]);
+
+        let (count, envs) = opts.select(b"PATH=/bin\0TOKEN=hunter2\0HOME=/root\0");
+
+        assert_eq!(count, 2);
+        assert_eq!(envs, b"HOME=/root\0TOKEN=<redacted>\0".to_vec());
+        assert_eq!(opts.select(&envs), (count, envs));
+    }
+
+    #[test]
+    fn validate_names() {
+        let long = "A".repeat(MAX_ENV_NAME_LEN);
+        assert!(EnvOpts::new(vec![long.clone(), format!("{}*", long)])
+            .validate()
+            .is_ok());
+        assert!(matches!(
+            EnvOpts::new(vec![format!("{}A", long)]).validate(),
+            Err(Error::EnvNameTooLong { .. })
+        ));
+        assert!(matches!(
+            EnvOpts::new(vec!["HOME"])
+                .redact(vec![format!("{}A*", long)])
+                .validate(),
+            Err(Error::EnvNameTooLong { .. })
+        ));
+
+        let names: Vec<_> = (0..MAX_ENV_NAMES).map(|i| format!("VAR_{}", i)).collect();
+        assert!(EnvOpts::new(names.clone()).redact(names.clone()).validate().is_ok());
+        let names: Vec<_> = (0..=MAX_ENV_NAMES).map(|i| format!("VAR_{}", i)).collect();
+        assert!(matches!(
+            EnvOpts::new(names).validate(),
+            Err(Error::TooManyEnvNames { .. })
+        ));
+    }
+
+    #[test]
+    fn kernel_names_like_env_key_t() {
+        let key = |len: u32, name: &str| {
+            let mut key = len.to_ne_bytes().to_vec();
+            key.extend_from_slice(name.as_bytes());
+            key.resize(4 + MAX_ENV_NAME_LEN, 0);
+            key
+        };
+        let opts = EnvOpts::new(vec!["HOME", "AWS_*", "*"]).redact(vec!["AWS_*", "TOKEN"]);
+
+        let mut names = opts.kernel_names();
+        names.sort();
+
+        let mut expected = vec![
+            (key(ENV_EXACT, "HOME"), vec![ENV_ALLOW]),
+            (key(4, "AWS_"), vec![ENV_ALLOW | ENV_REDACT]),
+            (key(0, ""), vec![ENV_ALLOW]),
+            (key(ENV_EXACT, "TOKEN"), vec![ENV_REDACT]),
+        ];
+        expected.sort();
+        assert_eq!(names, expected);
+        assert!(EnvOpts::default().redact(vec!["TOKEN"]).kernel_names().is_empty());
+    }
+}
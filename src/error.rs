@@ -34,6 +34,56 @@ pub enum Error {
         #[from]
         source: serde_json::error::Error,
     },
+    #[error("ancestor name '{name}' is longer than {max} characters")]
+    AncestorNameTooLong { name: String, max: usize },
+    #[error("duplicate ancestor name '{name}'")]
+    DuplicateAncestorName { name: String },
+    #[error("environment variable name '{name}' is longer than {max} characters")]
+    EnvNameTooLong { name: String, max: usize },
+    #[error("events of {size} bytes exceed the max size of {max} bytes; lower the number or length of arguments, ancestors, or environment variables")]
+    EventTooLarge { size: usize, max: usize },
+    #[error("event handler panicked: {msg}")]
+    HandlerPanicked { msg: String },
+    #[error("invalid ancestor name '{name}'")]
+    InvalidAncestorName { name: String },
+    #[error("invalid attach strategy '{name}'")]
+    InvalidAttachStrategy { name: String },
+    #[error("invalid capture file: {reason}")]
+    InvalidCapture { reason: String },
+    #[error("invalid comm '{comm}'; must be 1 to 15 characters and not both allowed and denied")]
+    InvalidComm { comm: String },
+    #[error("invalid encoding '{name}'")]
+    InvalidEncoding { name: String },
+    #[error("invalid exit mode '{name}'")]
+    InvalidExitMode { name: String },
+    #[error("invalid filter '{content}' in line {line}")]
+    InvalidFilter { line: usize, content: String },
+    #[error("invalid max ancestors {value}; must be between 1 and {max}")]
+    InvalidMaxAncestors { value: u32, max: u32 },
+    #[error("invalid max arg len {value}; must be between 2 and the args budget of {max}")]
+    InvalidMaxArgLen { value: u32, max: u32 },
+    #[error("invalid max args {value}; must be between 1 and {max}")]
+    InvalidMaxArgs { value: u32, max: u32 },
+    #[error("invalid max envs {value}; must be at most {max}")]
+    InvalidMaxEnvs { value: u32, max: u32 },
+    #[error("invalid path prefix '{prefix}'; must be 1 to 63 characters")]
+    InvalidPathPrefix { prefix: String },
+    #[error("invalid replay speed '{name}'")]
+    InvalidReplaySpeed { name: String },
+    #[error("invalid time format '{name}'")]
+    InvalidTimeFormat { name: String },
+    #[error("invalid transport '{name}'")]
+    InvalidTransport { name: String },
+    #[error("invalid uid range '{range}'")]
+    InvalidUidRange { range: String },
+    #[error("malformed event: {reason}")]
+    MalformedEvent { reason: String },
+    #[error("too many ancestor names; at most {max} are supported")]
+    TooManyAncestorNames { max: usize },
+    #[error("too many environment variable names; at most {max} are supported")]
+    TooManyEnvNames { max: usize },
+    #[error("too many {filter} filters; at most {max} are supported")]
+    TooManyFilterValues { filter: &'static str, max: usize },
     #[error("run time error because {msg}")]
     RunTimeError { msg: &'static str },
 }
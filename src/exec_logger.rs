@@ -12,68 +12,390 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use log::debug;
+use log::{debug, error, warn};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::ffi::{OsStr, OsString};
+use std::fmt;
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::str::FromStr;
 use std::sync::atomic::Ordering;
 use std::sync::{atomic::AtomicBool, Arc, Mutex};
 use std::thread;
 use std::thread::JoinHandle;
 
+use crate::assembler::ExecAssembler;
+use crate::capture::CaptureWriter;
+use crate::env::EnvOpts;
 use crate::output::Output;
+use crate::source::{EventHandler, EventSource, KProbeSource, LostHandler, SourceContext};
 use crate::{bpf, Error, Result};
-use std::time::Duration;
+use std::time::{Duration, SystemTime};
 
-#[derive(Debug)]
-pub enum Event {
-    Arg(Arg),
-    Return(Return),
+/// Syscall used to exec a program.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Syscall {
+    /// The attach strategy cannot tell, cf. `bpf::AttachStrategy::SchedTracepoint`
+    Unknown,
+    Execve,
+    Execveat,
 }
 
-#[derive(Debug)]
-pub struct Arg {
-    pub(crate) pid: u32,
-    pub(crate) argv: String,
+impl From<bpf::ExecVariant> for Syscall {
+    fn from(variant: bpf::ExecVariant) -> Self {
+        match variant {
+            bpf::ExecVariant::VARIANT_UNKNOWN => Syscall::Unknown,
+            bpf::ExecVariant::VARIANT_EXECVE => Syscall::Execve,
+            bpf::ExecVariant::VARIANT_EXECVEAT => Syscall::Execveat,
+        }
+    }
+}
+
+/// Parent of an exec'ed process.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Ancestor {
+    pub pid: u32,
+    pub comm: String,
+}
+
+impl From<bpf::Ancestor> for Ancestor {
+    fn from(ancestor: bpf::Ancestor) -> Self {
+        Ancestor {
+            pid: ancestor.pid,
+            comm: bpf::parse_string(&ancestor.comm),
+        }
+    }
 }
 
 #[derive(Debug)]
 pub struct Return {
+    /// Start of exec
+    pub timestamp: SystemTime,
     pub pid: u32,
+    /// Thread which called exec; after a successful exec by another thread than the leader, it continues as `pid`
+    pub tid: u32,
     pub ppid: u32,
-    pub ancestor: bool,
+    /// Ancestor name matched by the nearest ancestor
+    pub ancestor: Option<bpf::AncestorName>,
+    /// Parents starting with the oldest one; limited by `max_ancestors`
+    pub ancestors: Vec<Ancestor>,
     pub comm: String,
     pub tty: String,
     pub uid: u32,
     pub gid: u32,
     pub ret_val: i32,
+    pub syscall: Syscall,
+    /// Set if execveat has been called with `AT_EMPTY_PATH`, i.e., the program has been exec'ed from a file
+    /// descriptor, e.g., by `fexecve`
+    pub empty_path: bool,
+    /// Current working directory; starts with ".../" if it could not be resolved completely
+    pub cwd: Option<OsString>,
+    /// Set if `cwd` has been read from procfs after the exec, because the kernel could not resolve it; the process might
+    /// have changed its directory in the meantime
+    pub cwd_from_procfs: bool,
+    /// Absolute path of the exec'ed file, i.e., the filename resolved against `cwd`; `None` if `cwd` is incomplete
+    pub path: Option<OsString>,
+    /// Filename passed to exec, resolved against the path of dirfd for execveat; empty if it could not be read
+    pub filename: OsString,
+    /// Arguments starting with argv[0]
+    pub argv: Vec<OsString>,
+    /// Set if `argv` is incomplete: there have been more arguments than `max_args`, one has been cut off, or reading
+    /// one failed
+    pub truncated: bool,
+    /// Environment variables selected by `EnvOpts`
+    pub env: BTreeMap<String, OsString>,
+}
+
+impl Return {
+    /// Creates a return from an exec event; `procfs` allows resolving an incomplete cwd from procfs, which only makes
+    /// sense for events of processes which are still around, i.e., events of `KProbeSource`.
+    pub fn from_exec(event: bpf::Exec, ancestor_ids: &bpf::AncestorIds, env_opts: &EnvOpts, procfs: bool) -> Self {
+        let mut args = bpf::parse_strings(&event.args).into_iter();
+        let mut filename = args.next().unwrap_or_default();
+        let argv = args.collect();
+        if event.variant == bpf::ExecVariant::VARIANT_EXECVEAT {
+            if let Some(dir) = bpf::parse_dentry_path(&event.dir) {
+                filename = resolve_at(&dir, &filename);
+            }
+        }
+        let comm = bpf::parse_string(&event.comm);
+        let (cwd, cwd_from_procfs) = match bpf::parse_dentry_path(&event.cwd) {
+            Some(cwd) if is_absolute(&cwd) => (Some(cwd), false),
+            // The kernel could not follow the path up to the root, but the process might still be around
+            cwd => match procfs.then(|| read_cwd(event.pid, &comm)).flatten() {
+                Some(procfs_cwd) => (Some(procfs_cwd), true),
+                None => (cwd, false),
+            },
+        };
+        let path = match &cwd {
+            _ if is_absolute(&filename) => Some(normalize(&filename)),
+            Some(cwd) if !filename.is_empty() && event.dirfd == libc::AT_FDCWD && is_absolute(cwd) => {
+                Some(normalize(&resolve_at(cwd, &filename)))
+            }
+            _ => None,
+        };
+
+        Return {
+            timestamp: SystemTime::UNIX_EPOCH + Duration::from_nanos(event.ts),
+            pid: event.pid,
+            tid: event.tid,
+            ppid: event.ppid,
+            ancestor: ancestor_ids.resolve(event.ancestor),
+            ancestors: event.ancestors.into_iter().rev().map(Into::into).collect(),
+            comm,
+            tty: bpf::parse_string(&event.tty),
+            uid: event.uid,
+            gid: event.gid,
+            ret_val: event.ret_val,
+            syscall: event.variant.into(),
+            empty_path: event.flags & libc::AT_EMPTY_PATH != 0,
+            cwd,
+            cwd_from_procfs,
+            path,
+            filename,
+            argv,
+            truncated: event.args_truncated,
+            env: env_opts.capture(&event.envs),
+        }
+    }
+
+    /// Filename followed by the arguments after argv[0] and "..." if truncated, e.g., `/bin/ls -l`
+    ///
+    /// Argument boundaries are lost, so this is meant for display only.
+    pub fn command_line(&self) -> OsString {
+        let args: Vec<&[u8]> = std::iter::once(self.filename.as_bytes())
+            .chain(self.argv.iter().skip(1).map(|x| x.as_bytes()))
+            .chain(self.truncated.then_some(b"...".as_ref()))
+            .collect();
+        OsString::from_vec(args.join(&b' '))
+    }
+
+    /// Checks whether argv[0] names a different program than the exec'ed file.
+    ///
+    /// Login shells prefix argv[0] with "-", which is ignored.
+    pub fn argv0_mismatch(&self) -> bool {
+        let basename = |x: &[u8]| x.rsplit(|&c| c == b'/').next().unwrap_or_default().to_vec();
+        match self.argv.first() {
+            Some(argv0) => {
+                let argv0 = argv0.as_bytes();
+                let argv0 = argv0.strip_prefix(b"-").unwrap_or(argv0);
+                basename(argv0) != basename(self.filename.as_bytes())
+            }
+            None => false,
+        }
+    }
+
+    /// Ancestors followed by the process itself, e.g., `sshd>bash>sudo>apt`
+    pub fn lineage(&self) -> String {
+        self.ancestors
+            .iter()
+            .map(|x| x.comm.as_str())
+            .chain(std::iter::once(self.comm.as_str()))
+            .collect::<Vec<_>>()
+            .join(">")
+    }
+}
+
+/// Resolves a pathname passed to exec relative to the path of its dirfd or the current working directory.
+fn resolve_at(dir: &OsStr, pathname: &OsStr) -> OsString {
+    if is_absolute(pathname) {
+        pathname.to_os_string()
+    } else if pathname.is_empty() {
+        // AT_EMPTY_PATH: dirfd refers to the program itself
+        dir.to_os_string()
+    } else {
+        let dir = dir.as_bytes();
+        let mut path = dir.strip_suffix(b"/").unwrap_or(dir).to_vec();
+        path.push(b'/');
+        path.extend_from_slice(pathname.as_bytes());
+        OsString::from_vec(path)
+    }
+}
+
+fn is_absolute(path: &OsStr) -> bool {
+    path.as_bytes().starts_with(b"/")
+}
+
+/// Reads the current working directory of a process from procfs unless its pid has been reused by another program.
+fn read_cwd(pid: u32, comm: &str) -> Option<OsString> {
+    let proc_comm = std::fs::read_to_string(format!("/proc/{}/comm", pid)).ok()?;
+    if proc_comm.trim_end_matches('\n') != comm {
+        return None;
+    }
+    std::fs::read_link(format!("/proc/{}/cwd", pid))
+        .ok()
+        .map(|x| x.into_os_string())
+}
+
+/// Removes "." components and duplicate slashes from an absolute path; ".." is kept, because of symlinks.
+fn normalize(path: &OsStr) -> OsString {
+    let components: Vec<_> = path
+        .as_bytes()
+        .split(|&c| c == b'/')
+        .filter(|x| !x.is_empty() && *x != b".")
+        .collect();
+    let mut path = b"/".to_vec();
+    path.extend(components.join(&b'/'));
+    OsString::from_vec(path)
+}
+
+/// Exit of a process which has been started by a traced exec.
+#[derive(Debug)]
+pub struct Exit {
+    /// End of process
+    pub timestamp: SystemTime,
+    /// Start of exec; identifies the corresponding `Return` together with `pid`
+    pub exec_timestamp: SystemTime,
+    pub pid: u32,
+    /// Ancestor name matched by the nearest ancestor at exec
+    pub ancestor: Option<bpf::AncestorName>,
+    pub comm: String,
+    /// Exit status; 0 if terminated by a signal
+    pub exit_code: i32,
+    /// Signal which terminated the process; 0 if exited normally
+    pub signal: i32,
+    pub user_time: Duration,
+    pub system_time: Duration,
+    /// Max resident set size in KiB
+    pub max_rss_kb: u64,
+}
+
+impl Exit {
+    pub fn from_exit(exit: bpf::Exit, ancestor_ids: &bpf::AncestorIds) -> Self {
+        let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) }.max(0) as u64;
+        Exit {
+            timestamp: SystemTime::UNIX_EPOCH + Duration::from_nanos(exit.ts),
+            exec_timestamp: SystemTime::UNIX_EPOCH + Duration::from_nanos(exit.start_ts),
+            pid: exit.pid,
+            ancestor: ancestor_ids.resolve(exit.ancestor),
+            comm: bpf::parse_string(&exit.comm),
+            exit_code: exit.exit_code,
+            signal: exit.signal,
+            user_time: Duration::from_nanos(exit.utime),
+            system_time: Duration::from_nanos(exit.stime),
+            max_rss_kb: exit.maxrss * page_size / 1024,
+        }
+    }
+
+    /// Wall-clock time from exec to exit.
+    pub fn duration(&self) -> Duration {
+        self.timestamp.duration_since(self.exec_timestamp).unwrap_or_default()
+    }
+}
+
+/// Defines how exits of processes are reported.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExitMode {
+    /// Exec and exit as separate records
+    Separate,
+    /// Exec and exit as one record as soon as the process has exited
+    Merged,
+}
+
+impl ExitMode {
+    pub const ALL: [ExitMode; 2] = [ExitMode::Separate, ExitMode::Merged];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ExitMode::Separate => "separate",
+            ExitMode::Merged => "merged",
+        }
+    }
+}
+
+impl fmt::Display for ExitMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for ExitMode {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        ExitMode::ALL
+            .iter()
+            .find(|x| x.name() == s.to_lowercase())
+            .copied()
+            .ok_or_else(|| Error::InvalidExitMode { name: s.to_string() })
+    }
+}
+
+/// Events dropped by the kernel, because the buffer to userspace was full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Lost {
+    pub cpu: u32,
+    pub count: u64,
 }
 
-impl From<bpf::Event> for Event {
-    fn from(event: bpf::Event) -> Self {
-        match event.r#type {
-            bpf::EventType::EVENT_ARG => Event::Arg(Arg {
-                pid: event.pid,
-                argv: bpf::parse_string(&event.argv),
-            }),
-            bpf::EventType::EVENT_RET => Event::Return(Return {
-                pid: event.pid,
-                ppid: event.ppid,
-                ancestor: event.ancestor != 0,
-                comm: bpf::parse_string(&event.comm),
-                tty: bpf::parse_string(&event.tty),
-                uid: event.uid,
-                gid: event.gid,
-                ret_val: event.ret_val,
-            }),
+impl From<bpf::LostEvents> for Lost {
+    fn from(lost: bpf::LostEvents) -> Self {
+        Lost {
+            cpu: lost.cpu,
+            count: lost.count,
         }
     }
 }
 
+/// Execs dropped by the kernel before they returned, cf. `bpf::DroppedExecs`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Dropped {
+    pub count: u64,
+}
+
+impl From<bpf::DroppedExecs> for Dropped {
+    fn from(dropped: bpf::DroppedExecs) -> Self {
+        Dropped { count: dropped.count }
+    }
+}
+
+/// Statistics of a run.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Stats {
+    /// Total number of lost events
+    pub lost_events: u64,
+    /// Number of lost events per CPU
+    pub lost_events_per_cpu: BTreeMap<u32, u64>,
+    /// Number of events that could not be decoded and have been dropped
+    pub malformed_events: u64,
+    /// Number of execs reported without exit, because it did not arrive in time, cf. `ExecLoggerOpts::max_pending`, and
+    /// of execs dropped by the kernel before they returned, cf. `Dropped`
+    pub incomplete_execs: u64,
+}
+
+impl Stats {
+    fn add_lost(&mut self, lost: &Lost) {
+        self.lost_events += lost.count;
+        *self.lost_events_per_cpu.entry(lost.cpu).or_insert(0) += lost.count;
+    }
+}
+
 #[derive(Debug)]
 pub struct ExecLoggerOpts {
     pub quiet: bool,
+    /// Max number of arguments to record; filters may lower it while running
     pub max_args: u32,
-    pub ancestor_name: String,
+    /// Max length of a single argument in bytes; longer ones are cut off and marked as truncated
+    pub max_arg_len: u32,
+    /// Total number of bytes for filename and arguments; further arguments are dropped and marked as truncated
+    pub args_budget: u32,
+    /// Initial filters, cf. `RunningExecLogger::filter_updater`
+    pub filters: bpf::Filters,
     pub max_ancestors: u32,
+    /// Environment variables to capture
+    pub env: EnvOpts,
     pub interval_ms: u32,
+    /// Attach strategy to use; if `None`, all strategies are tried in order.
+    pub attach: Option<bpf::AttachStrategy>,
+    /// Transport to use; if `None`, the ring buffer is used if supported by the kernel.
+    pub transport: Option<bpf::Transport>,
+    /// Reports exits of processes, if set
+    pub exits: Option<ExitMode>,
+    /// Max number of execs waiting for their exit in `ExitMode::Merged`; the oldest ones are reported as incomplete
+    pub max_pending: usize,
+    /// Time an exec waits for its exit in `ExitMode::Merged` before it is reported as incomplete
+    pub pending_ttl: Duration,
 }
 
 impl Default for ExecLoggerOpts {
@@ -81,9 +403,17 @@ impl Default for ExecLoggerOpts {
         ExecLoggerOpts {
             quiet: false,
             max_args: 20,
-            ancestor_name: "sshd".to_string(),
+            max_arg_len: 1024,
+            args_budget: 8192,
+            filters: bpf::Filters::default(),
             max_ancestors: 20,
+            env: EnvOpts::default(),
             interval_ms: 200,
+            attach: None,
+            transport: None,
+            exits: None,
+            max_pending: ExecAssembler::DEFAULT_MAX_PENDING,
+            pending_ttl: ExecAssembler::DEFAULT_PENDING_TTL,
         }
     }
 }
@@ -93,82 +423,245 @@ pub struct ExecLogger<T: Output + Send + 'static> {
     runnable: Arc<AtomicBool>,
     opts: ExecLoggerOpts,
     output: T,
+    source: Box<dyn EventSource>,
+    capture: Option<CaptureWriter>,
 }
 
 impl<T: Output + Send + 'static> ExecLogger<T> {
     pub fn new(opts: ExecLoggerOpts, output: T) -> Self {
         let runnable = Arc::new(AtomicBool::new(true));
-        ExecLogger { runnable, opts, output }
+        ExecLogger {
+            runnable,
+            opts,
+            output,
+            source: Box::new(KProbeSource),
+            capture: None,
+        }
+    }
+
+    /// Sets the source of events; defaults to `KProbeSource`
+    pub fn source<S: EventSource + 'static>(mut self, source: S) -> Self {
+        self.source = Box::new(source);
+        self
+    }
+
+    /// Records all events and lost events to a capture, cf. `source::CaptureSource`; the environment variables are
+    /// recorded as selected by `ExecLoggerOpts::env`
+    pub fn record(mut self, capture: CaptureWriter) -> Self {
+        self.capture = Some(capture);
+        self
     }
 
     pub fn run(mut self) -> Result<RunningExecLogger> {
+        let kprobe_opts = bpf::KProbeOpts {
+            max_args: self.opts.max_args,
+            max_arg_len: self.opts.max_arg_len,
+            args_budget: self.opts.args_budget,
+            filters: self.opts.filters.clone(),
+            max_ancestors: self.opts.max_ancestors,
+            env: self.opts.env.clone(),
+            interval_ms: self.opts.interval_ms,
+            attach: self.opts.attach,
+            transport: self.opts.transport,
+            trace_exit: self.opts.exits.is_some(),
+        };
+        kprobe_opts.validate()?;
+        let (filter_updater, filter_updates) = bpf::filter_channel(kprobe_opts.filters.clone(), kprobe_opts.max_args);
+        let ancestor_ids = bpf::AncestorIds::default();
+
         if !self.opts.quiet {
             self.output.header()?;
         }
 
         let output = Arc::new(Mutex::new(self.output));
+        let stats = Arc::new(Mutex::new(Stats::default()));
+
+        let assembler = ExecAssembler::new(ancestor_ids.clone(), self.opts.env.clone(), self.opts.exits)
+            .max_pending(self.opts.max_pending)
+            .pending_ttl(self.opts.pending_ttl)
+            .procfs(self.source.live());
+        let assembler = Arc::new(Mutex::new(assembler));
+        let env_opts = self.opts.env.clone();
+        let capture = self.capture.map(|x| Arc::new(Mutex::new(x.env(env_opts))));
+
+        let failure = OutputFailure::new(self.runnable.clone());
 
-        let handler = move |event: bpf::Event| {
-            let event: Event = event.into();
-            let mut output = output.lock().unwrap();
-            match event {
-                Event::Arg(a) => {
-                    debug!("Entry/Arg event: {:?}", a);
-                    output.arg(a).unwrap()
+        let ret_output = output.clone();
+        let ret_assembler = assembler.clone();
+        let ret_stats = stats.clone();
+        let ret_capture = capture.clone();
+        let ret_failure = failure.clone();
+        let handler: EventHandler = Arc::new(move |event: bpf::Event| {
+            if ret_failure.failed() {
+                return;
+            }
+            if let Some(capture) = &ret_capture {
+                if let Err(err) = capture.lock().unwrap().write_event(&event) {
+                    warn!("Failed to record event: {}", err);
                 }
-                Event::Return(r) => {
-                    debug!("Return event: {:?}", r);
-                    output.ret(r).unwrap()
+            }
+            if let bpf::Event::Malformed(malformed) = &event {
+                warn!(
+                    "Dropped malformed event of {} bytes: {}",
+                    malformed.size, malformed.error
+                );
+                ret_stats.lock().unwrap().malformed_events += 1;
+                return;
+            }
+            if let bpf::Event::Dropped(dropped) = &event {
+                warn!("Dropped {} execs before they returned", dropped.count);
+            }
+            let mut output = ret_output.lock().unwrap();
+            let mut assembler = ret_assembler.lock().unwrap();
+            ret_failure.check(assembler.push(event, &mut *output));
+            ret_stats.lock().unwrap().incomplete_execs = assembler.evicted() + assembler.dropped();
+        });
+
+        let lost_stats = stats.clone();
+        let lost_output = output.clone();
+        let lost_capture = capture.clone();
+        let lost_failure = failure.clone();
+        let lost_handler: LostHandler = Arc::new(move |lost: bpf::LostEvents| {
+            if lost_failure.failed() {
+                return;
+            }
+            if let Some(capture) = &lost_capture {
+                if let Err(err) = capture.lock().unwrap().write_lost(&lost) {
+                    warn!("Failed to record lost events: {}", err);
                 }
             }
-        };
+            let lost: Lost = lost.into();
+            warn!("Lost {} events on CPU {}", lost.count, lost.cpu);
+            lost_stats.lock().unwrap().add_lost(&lost);
+            let mut output = lost_output.lock().unwrap();
+            lost_failure.check(output.lost(lost));
+        });
 
-        let kprobe_opts = bpf::KProbeOpts {
-            max_args: self.opts.max_args,
-            ancestor_name: self.opts.ancestor_name.clone(),
-            max_ancestors: self.opts.max_ancestors,
-            interval_ms: self.opts.interval_ms,
+        let context = SourceContext {
+            runnable: self.runnable.clone(),
+            opts: kprobe_opts,
+            filter_updates,
+            ancestor_ids,
         };
-        let kprobe = bpf::KProbe::new(self.runnable.clone(), handler, kprobe_opts);
+        let source = self.source;
 
         let thread_name = format!("{}-logging", env!("CARGO_PKG_NAME"));
         let thread = thread::Builder::new().name(thread_name);
         let join_handle = thread.spawn(move || {
             debug!("Started logging thread");
-            kprobe.run()
+            source.run(context, handler, lost_handler)?;
+            if let Some(capture) = capture {
+                capture.lock().unwrap().flush()?;
+            }
+            if let Some(err) = failure.take() {
+                return Err(err);
+            }
+            // Processes still running at the end are reported without exit
+            let mut output = output.lock().map_err(|_| Error::RunTimeError {
+                msg: "failed to write output",
+            })?;
+            assembler.lock().unwrap().finish(&mut *output)?;
+            Ok(())
         })?;
 
-        Ok(RunningExecLogger::new(self.runnable, join_handle))
+        Ok(RunningExecLogger::new(
+            self.runnable,
+            stats,
+            filter_updater,
+            join_handle,
+        ))
+    }
+}
+
+/// First error of the output; it stops the logger, so records are not dropped silently.
+#[derive(Debug, Clone)]
+struct OutputFailure {
+    error: Arc<Mutex<Option<Error>>>,
+    runnable: Arc<AtomicBool>,
+}
+
+impl OutputFailure {
+    fn new(runnable: Arc<AtomicBool>) -> OutputFailure {
+        OutputFailure {
+            error: Arc::new(Mutex::new(None)),
+            runnable,
+        }
+    }
+
+    fn failed(&self) -> bool {
+        self.error.lock().map(|x| x.is_some()).unwrap_or(true)
+    }
+
+    fn check(&self, result: Result<()>) {
+        if let Err(err) = result {
+            error!("Failed to write output, stopping: {}", err);
+            if let Ok(mut error) = self.error.lock() {
+                error.get_or_insert(err);
+            }
+            self.runnable.store(false, Ordering::SeqCst);
+        }
+    }
+
+    fn take(&self) -> Option<Error> {
+        self.error.lock().ok().and_then(|mut x| x.take())
     }
 }
 
 #[derive(Debug)]
 pub struct RunningExecLogger {
     runnable: Arc<AtomicBool>,
+    stats: Arc<Mutex<Stats>>,
+    filter_updater: bpf::FilterUpdater,
     join_handle: JoinHandle<Result<()>>,
 }
 
 impl RunningExecLogger {
-    pub fn new(runnable: Arc<AtomicBool>, join_handle: JoinHandle<Result<()>>) -> RunningExecLogger {
-        RunningExecLogger { runnable, join_handle }
+    pub fn new(
+        runnable: Arc<AtomicBool>,
+        stats: Arc<Mutex<Stats>>,
+        filter_updater: bpf::FilterUpdater,
+        join_handle: JoinHandle<Result<()>>,
+    ) -> RunningExecLogger {
+        RunningExecLogger {
+            runnable,
+            stats,
+            filter_updater,
+            join_handle,
+        }
     }
 
     pub fn stopper(&self) -> Arc<AtomicBool> {
         self.runnable.clone()
     }
 
-    pub fn wait(self) -> Result<()> {
+    /// Returns a handle to change the filters while running.
+    pub fn filter_updater(&self) -> bpf::FilterUpdater {
+        self.filter_updater.clone()
+    }
+
+    /// Waits for the logging thread to finish and returns the statistics of the run.
+    pub fn wait(self) -> Result<Stats> {
         self.join_handle.join().map_err(|_| Error::RunTimeError {
             msg: "failed to synchronize with logging thread",
-        })?
+        })??;
+        Self::into_stats(self.stats)
     }
 
-    pub fn wait_n_stop(self, time: Duration) -> Result<()> {
+    /// Stops the logging thread after `time` and returns the statistics of the run.
+    pub fn wait_n_stop(self, time: Duration) -> Result<Stats> {
         thread::sleep(time);
         self.runnable.stop();
         self.join_handle.join().map_err(|_| Error::RunTimeError {
             msg: "failed to synchronize with logging thread",
-        })?
+        })??;
+        Self::into_stats(self.stats)
+    }
+
+    fn into_stats(stats: Arc<Mutex<Stats>>) -> Result<Stats> {
+        let stats = stats.lock().map_err(|_| Error::RunTimeError {
+            msg: "failed to collect statistics",
+        })?;
+        Ok(stats.clone())
     }
 }
 
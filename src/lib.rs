@@ -12,13 +12,22 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+pub mod assembler;
 pub mod bpf;
+pub mod capture;
+pub mod env;
 pub mod error;
 pub mod exec_logger;
 pub mod logging;
 pub mod output;
+pub mod source;
 
+pub use crate::assembler::{ExecAssembler, ExecEvent};
+pub use crate::bpf::{AncestorName, FilterUpdater, Filters, MatchMode, UidRange};
+pub use crate::env::EnvOpts;
 pub use crate::error::Error;
-pub use crate::exec_logger::{Arg, ExecLogger, ExecLoggerOpts, Return, RunningExecLogger, Stopper};
+pub use crate::exec_logger::{
+    Ancestor, Dropped, ExecLogger, ExecLoggerOpts, Exit, ExitMode, Lost, Return, RunningExecLogger, Stats, Stopper,
+};
 
 pub type Result<T> = std::result::Result<T, Error>;
@@ -13,12 +13,23 @@
 // limitations under the License.
 
 use anyhow::{Context, Result};
+use exec_logger::bpf::{AttachStrategy, Transport, DEFAULT_ANCESTOR};
+use exec_logger::capture::{CaptureWriter, ReplaySpeed};
 use exec_logger::logging;
-use exec_logger::output::{JsonLinesOutput, JsonLinesOutputOpts, TableOutput, TableOutputOpts};
-use exec_logger::{ExecLogger, ExecLoggerOpts, Stopper};
-use log::{debug, info};
-use std::io;
+use exec_logger::output::{
+    Encoding, JsonLinesOutput, JsonLinesOutputOpts, Output, TableOutput, TableOutputOpts, TimeFormat,
+};
+use exec_logger::source::CaptureSource;
+use exec_logger::{
+    AncestorName, EnvOpts, ExecLogger, ExecLoggerOpts, ExitMode, FilterUpdater, Filters, RunningExecLogger, Stopper,
+    UidRange,
+};
+use log::{debug, info, warn};
+use signal_hook::consts::SIGHUP;
+use signal_hook::iterator::Signals;
+use std::path::{Path, PathBuf};
 use std::time::Duration;
+use std::{fs, io, thread};
 use structopt::StructOpt;
 
 #[derive(Debug, StructOpt)]
@@ -27,21 +38,101 @@ struct Args {
     /// Sets max number of syscall arguments to parse
     #[structopt(long, value_name = "NUMBER", default_value = "20")]
     pub max_args: u32,
-    /// Sets name of ancestor to filter
-    #[structopt(long, value_name = "BINARY NAME", default_value = "sshd")]
-    pub ancestor: String,
-    /// Sets max number ancestors to check for ancestor name
+    /// Sets max length of a single argument in bytes
+    #[structopt(long, value_name = "BYTES", default_value = "1024")]
+    pub max_arg_len: u32,
+    /// Sets max number of bytes of all arguments together
+    #[structopt(long, value_name = "BYTES", default_value = "8192")]
+    pub args_budget: u32,
+    /// Sets names of ancestors to look for; a trailing * matches prefixes, =LABEL is reported on matches; may be
+    /// given multiple times
+    #[structopt(long, value_name = "NAME[*][=LABEL]", default_value = DEFAULT_ANCESTOR, number_of_values = 1)]
+    pub ancestor: Vec<AncestorName>,
+    /// Reads filters from file instead of --ancestor and the other filter options and reloads them on SIGHUP
+    #[structopt(long, value_name = "FILE")]
+    pub filter_file: Option<PathBuf>,
+    /// Sets max number of ancestors to record and to check for ancestor name
     #[structopt(long, value_name = "NUMBER", default_value = "20")]
     pub max_ancestors: u32,
     /// Displays only processes with expected ancestor
     #[structopt(long)]
     pub only_ancestor: bool,
+    /// Captures this environment variable of processes; a trailing * matches prefixes; may be given multiple times
+    #[structopt(long, value_name = "NAME[*]", number_of_values = 1)]
+    pub env: Vec<String>,
+    /// Replaces the value of this captured environment variable; a trailing * matches prefixes; may be given multiple
+    /// times
+    #[structopt(long, value_name = "NAME[*]", number_of_values = 1)]
+    pub env_redact: Vec<String>,
+    /// Sets max length of captured environment variable values
+    #[structopt(long, value_name = "BYTES", default_value = "128")]
+    pub env_max_len: usize,
+    /// Sets max number of environment variables to read per process
+    #[structopt(long, value_name = "NUMBER", default_value = "64")]
+    pub max_envs: u32,
+    /// Adds a table column for this captured environment variable; may be given multiple times
+    #[structopt(long, value_name = "NAME", number_of_values = 1)]
+    pub env_column: Vec<String>,
+    /// Displays only processes run by uids in this range, e.g., 1000-1999; may be given multiple times
+    #[structopt(long, value_name = "UID[-UID]", number_of_values = 1)]
+    pub uid: Vec<UidRange>,
+    /// Displays only processes run by this gid; may be given multiple times
+    #[structopt(long, value_name = "GID", number_of_values = 1)]
+    pub gid: Vec<u32>,
+    /// Displays only processes with this comm; may be given multiple times
+    #[structopt(long, value_name = "COMM", number_of_values = 1)]
+    pub comm_allow: Vec<String>,
+    /// Hides processes with this comm; may be given multiple times
+    #[structopt(long, value_name = "COMM", number_of_values = 1)]
+    pub comm_deny: Vec<String>,
+    /// Displays only processes whose filename starts with this prefix; may be given multiple times
+    #[structopt(long, value_name = "PREFIX", number_of_values = 1)]
+    pub path_prefix: Vec<String>,
+    /// Sets how to attach to exec; tries syscalls, sched, and kprobe in this order if not set
+    #[structopt(long, value_name = "STRATEGY", possible_values = &["syscalls", "sched", "kprobe"])]
+    pub attach: Option<AttachStrategy>,
+    /// Sets how to receive events from the kernel; uses ring if supported by the kernel and perf otherwise if not set
+    #[structopt(long, value_name = "TRANSPORT", possible_values = &["ring", "perf"])]
+    pub transport: Option<Transport>,
+    /// Reports exits of processes either as separate records or merged with their exec
+    #[structopt(long, value_name = "MODE", possible_values = &["separate", "merged"])]
+    pub exits: Option<ExitMode>,
+    /// Sets the max number of execs waiting for their exit with merged exits; the oldest ones are reported as incomplete
+    #[structopt(long, value_name = "NUMBER", default_value = "16384")]
+    pub max_pending: usize,
+    /// Sets how long execs wait for their exit with merged exits before they are reported as incomplete
+    #[structopt(long, value_name = "SECONDS", default_value = "3600")]
+    pub pending_ttl: u64,
     /// Sets event poll timer interval in ms
     #[structopt(long, value_name = "MILLISECONDS", default_value = "200")]
     pub interval: u32,
+    /// Records all events to this capture file; only environment variables selected by --env are recorded
+    #[structopt(long, value_name = "FILE")]
+    pub record: Option<PathBuf>,
+    /// Replays events from this capture file instead of tracing execs; filters are applied to the recorded events
+    #[structopt(long, value_name = "FILE")]
+    pub replay: Option<PathBuf>,
+    /// Replays events with the recorded delays or as fast as possible
+    #[structopt(long, value_name = "SPEED", default_value = "original", possible_values = &["original", "fast"])]
+    pub replay_speed: ReplaySpeed,
     /// Sets output format
     #[structopt(long, value_name = "FORMAT  ", default_value = "table", possible_values = &["table", "json"])]
     pub output: String,
+    /// Sets format of timestamps
+    #[structopt(long, value_name = "FORMAT", default_value = "rfc3339", possible_values = &["rfc3339", "epoch-millis", "relative"])]
+    pub time_format: TimeFormat,
+    /// Sets encoding of filenames, arguments, paths, and environment variables
+    #[structopt(long, value_name = "ENCODING", default_value = "escaped", possible_values = &["escaped", "base64", "lossy"])]
+    pub encoding: Encoding,
+    /// Quotes arguments in table output like a shell would need them
+    #[structopt(long)]
+    pub shell_quote: bool,
+    /// Writes control characters to the terminal as they are instead of escaping them in table output
+    #[structopt(long)]
+    pub no_escape: bool,
+    /// Adds the arguments joined by spaces as field args to JSON output like earlier versions
+    #[structopt(long)]
+    pub legacy_args: bool,
     /// Sets numeric output for uid and gid
     #[structopt(short, long)]
     pub numeric: bool,
@@ -93,21 +184,32 @@ fn main() {
 }
 
 fn run(args: &Args) -> Result<()> {
-    let opts = args.into();
+    let mut opts: ExecLoggerOpts = args.into();
+    if let Some(path) = &args.filter_file {
+        opts.filters = read_filters(path)?;
+    }
     let logger = match args.output.to_lowercase().as_str() {
         "json" => {
             debug!("Using JSON Lines output");
             let stdout = io::stdout();
-            let output_opts = JsonLinesOutputOpts::new(stdout, args.only_ancestor, args.numeric);
+            let output_opts = JsonLinesOutputOpts::new(stdout, args.only_ancestor, args.numeric)
+                .time_format(args.time_format)
+                .legacy_args(args.legacy_args)
+                .encoding(args.encoding);
             let output = JsonLinesOutput::new(output_opts);
-            ExecLogger::new(opts, output).run()
+            start(args, opts, output)
         }
         _ => {
             debug!("Using table output");
             let stdout = io::stdout();
-            let output_opts = TableOutputOpts::new(stdout, args.only_ancestor, args.numeric);
+            let output_opts = TableOutputOpts::new(stdout, args.only_ancestor, args.numeric)
+                .time_format(args.time_format)
+                .env_columns(args.env_column.clone())
+                .shell_quote(args.shell_quote)
+                .escape_control(!args.no_escape)
+                .encoding(args.encoding);
             let output = TableOutput::new(output_opts);
-            ExecLogger::new(opts, output).run()
+            start(args, opts, output)
         }
     }
     .context("Failed to run logger")?;
@@ -121,26 +223,104 @@ fn run(args: &Args) -> Result<()> {
     })
     .context("Failed to set handler for SIGINT / SIGTERM")?;
 
-    if let Some(wait) = args.wait {
+    if let Some(path) = &args.filter_file {
+        reload_filters_on_sighup(path.clone(), logger.filter_updater())?;
+    }
+
+    let stats = if let Some(wait) = args.wait {
         info!("Running event loop {} seconds.", wait);
-        logger.wait_n_stop(Duration::from_secs(wait))?;
+        logger.wait_n_stop(Duration::from_secs(wait))?
     } else {
         info!("Waiting for event loop to finish.");
-        logger.wait()?;
+        logger.wait()?
+    };
+    if stats.lost_events > 0 {
+        warn!("Lost {} events in total.", stats.lost_events);
+    }
+    if stats.malformed_events > 0 {
+        warn!("Dropped {} malformed events in total.", stats.malformed_events);
+    }
+    if stats.incomplete_execs > 0 {
+        warn!(
+            "Missed the return or exit of {} execs in total.",
+            stats.incomplete_execs
+        );
     }
     info!("Finished.");
 
     Ok(())
 }
 
+fn start<T: Output + Send + 'static>(args: &Args, opts: ExecLoggerOpts, output: T) -> Result<RunningExecLogger> {
+    let mut logger = ExecLogger::new(opts, output);
+    if let Some(path) = &args.replay {
+        info!("Replaying events from {}", path.display());
+        let source = CaptureSource::open(path, args.replay_speed)
+            .with_context(|| format!("Failed to open capture {}", path.display()))?;
+        logger = logger.source(source);
+    }
+    if let Some(path) = &args.record {
+        info!("Recording events to {}", path.display());
+        let capture =
+            CaptureWriter::create(path).with_context(|| format!("Failed to create capture {}", path.display()))?;
+        logger = logger.record(capture);
+    }
+
+    Ok(logger.run()?)
+}
+
+fn read_filters(path: &Path) -> Result<Filters> {
+    let filters = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read filters from {}", path.display()))?
+        .parse()
+        .with_context(|| format!("Failed to parse filters from {}", path.display()))?;
+
+    Ok(filters)
+}
+
+fn reload_filters_on_sighup(path: PathBuf, filter_updater: FilterUpdater) -> Result<()> {
+    let mut signals = Signals::new([SIGHUP]).context("Failed to set handler for SIGHUP")?;
+    thread::spawn(move || {
+        for _ in signals.forever() {
+            info!("Reloading filters from {}", path.display());
+            let res = read_filters(&path).and_then(|x| Ok(filter_updater.set(x)?));
+            if let Err(err) = res {
+                warn!("Failed to reload filters: {:?}", err);
+            }
+        }
+    });
+
+    Ok(())
+}
+
 impl From<&Args> for ExecLoggerOpts {
     fn from(args: &Args) -> Self {
         ExecLoggerOpts {
             quiet: args.quiet,
             max_args: args.max_args,
-            ancestor_name: args.ancestor.clone(),
+            max_arg_len: args.max_arg_len,
+            args_budget: args.args_budget,
+            filters: Filters {
+                ancestor_names: args.ancestor.clone(),
+                only_ancestor: args.only_ancestor,
+                max_args: None,
+                uid_ranges: args.uid.clone(),
+                gids: args.gid.clone(),
+                comm_allow: args.comm_allow.clone(),
+                comm_deny: args.comm_deny.clone(),
+                path_prefixes: args.path_prefix.clone(),
+            },
             max_ancestors: args.max_ancestors,
+            env: EnvOpts::new(args.env.iter().chain(args.env_column.iter()).cloned())
+                .redact(args.env_redact.clone())
+                .max_value_len(args.env_max_len)
+                .max_vars(args.max_envs),
             interval_ms: args.interval,
+            attach: args.attach,
+            transport: args.transport,
+            exits: args.exits,
+            max_pending: args.max_pending,
+            pending_ttl: Duration::from_secs(args.pending_ttl),
         }
     }
 }
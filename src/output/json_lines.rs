@@ -13,12 +13,15 @@
 // limitations under the License.
 
 use serde::Serialize;
-use std::collections::HashMap;
-use std::io::Write;
+use serde_json::ser::Formatter;
+use std::collections::BTreeMap;
+use std::io::{self, Write};
 use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
 
-use crate::output::{Group, Output, ToName, User};
-use crate::{Arg, Return};
+use crate::exec_logger::Syscall;
+use crate::output::{Encoding, Group, Output, Time, TimeFormat, ToName, User};
+use crate::{Ancestor, AncestorName, Dropped, ExecEvent, Exit, Lost, Return};
 use crate::{Error, Result};
 
 #[derive(Debug)]
@@ -26,6 +29,9 @@ pub struct JsonLinesOutputOpts<T: Write> {
     writer: Arc<Mutex<T>>,
     only_ancestor: bool,
     numeric: bool,
+    time_format: TimeFormat,
+    legacy_args: bool,
+    encoding: Encoding,
 }
 
 impl<T: Write> JsonLinesOutputOpts<T> {
@@ -34,21 +40,41 @@ impl<T: Write> JsonLinesOutputOpts<T> {
             writer: Arc::new(Mutex::new(writer)),
             only_ancestor,
             numeric,
+            time_format: TimeFormat::Rfc3339,
+            legacy_args: false,
+            encoding: Encoding::Escaped,
         }
     }
+
+    pub fn time_format(mut self, time_format: TimeFormat) -> JsonLinesOutputOpts<T> {
+        self.time_format = time_format;
+        self
+    }
+
+    /// Sets the encoding of filename, arguments, paths, and environment variables
+    pub fn encoding(mut self, encoding: Encoding) -> JsonLinesOutputOpts<T> {
+        self.encoding = encoding;
+        self
+    }
+
+    /// Adds the field `args` with filename and arguments joined by spaces as written by earlier versions
+    pub fn legacy_args(mut self, legacy_args: bool) -> JsonLinesOutputOpts<T> {
+        self.legacy_args = legacy_args;
+        self
+    }
 }
 
 #[derive(Debug)]
 pub struct JsonLinesOutput<T: Write> {
-    args: Arc<Mutex<HashMap<u32, Vec<String>>>>,
     opts: JsonLinesOutputOpts<T>,
+    start: SystemTime,
 }
 
 impl<T: Write> JsonLinesOutput<T> {
     pub fn new(opts: JsonLinesOutputOpts<T>) -> Self {
         JsonLinesOutput {
-            args: Arc::new(Mutex::new(HashMap::new())),
             opts,
+            start: SystemTime::UNIX_EPOCH,
         }
     }
 }
@@ -58,30 +84,109 @@ impl<T: Write> Output for JsonLinesOutput<T> {
         Ok(())
     }
 
-    fn arg(&mut self, arg: Arg) -> Result<()> {
-        let mut args = self.args.lock().map_err(|_| Error::RunTimeError {
-            msg: "failed to collect for output",
+    fn start(&mut self, time: SystemTime) -> Result<()> {
+        self.start = time;
+        Ok(())
+    }
+
+    fn exec(&mut self, event: ExecEvent) -> Result<()> {
+        self.write_ret(event)
+    }
+
+    fn exit(&mut self, exit: Exit) -> Result<()> {
+        let mut writer = self.opts.writer.lock().map_err(|_| Error::RunTimeError {
+            msg: "failed to write output",
+        })?;
+
+        if !self.opts.only_ancestor || exit.ancestor.is_some() {
+            let json_line = JsonExit::new(exit, self.opts.time_format, self.start);
+            let json_line = to_json(&json_line)?;
+            writeln!(writer, "{}", json_line)?;
+        }
+
+        Ok(())
+    }
+
+    fn lost(&mut self, lost: Lost) -> Result<()> {
+        let mut writer = self.opts.writer.lock().map_err(|_| Error::RunTimeError {
+            msg: "failed to write output",
         })?;
-        let value = args.entry(arg.pid).or_insert_with(Vec::new);
-        value.push(arg.argv);
+
+        let json_line = JsonLost::from(lost);
+        let json_line = to_json(&json_line)?;
+        writeln!(writer, "{}", json_line)?;
 
         Ok(())
     }
 
-    fn ret(&mut self, ret: Return) -> Result<()> {
+    fn dropped(&mut self, dropped: Dropped) -> Result<()> {
         let mut writer = self.opts.writer.lock().map_err(|_| Error::RunTimeError {
             msg: "failed to write output",
         })?;
 
-        let mut args = self.args.lock().map_err(|_| Error::RunTimeError {
-            msg: "failed to collect for output",
+        let json_line = JsonDropped::from(dropped);
+        let json_line = to_json(&json_line)?;
+        writeln!(writer, "{}", json_line)?;
+
+        Ok(())
+    }
+}
+
+/// Serializes like `serde_json::to_string`, but also escapes DEL and C1 control characters.
+///
+/// serde_json leaves U+007F to U+009F unescaped; written raw, they may be interpreted by terminals, e.g., when piped
+/// to `less -R`.
+fn to_json<S: Serialize>(value: &S) -> Result<String> {
+    let mut buf = Vec::new();
+    let mut serializer = serde_json::Serializer::with_formatter(&mut buf, EscapeControlFormatter);
+    value.serialize(&mut serializer)?;
+    // serde_json only writes valid UTF-8
+    Ok(String::from_utf8_lossy(&buf).into_owned())
+}
+
+struct EscapeControlFormatter;
+
+impl Formatter for EscapeControlFormatter {
+    fn write_string_fragment<W: ?Sized + io::Write>(&mut self, writer: &mut W, fragment: &str) -> io::Result<()> {
+        let mut start = 0;
+        for (i, c) in fragment.char_indices() {
+            if c.is_control() {
+                writer.write_all(&fragment.as_bytes()[start..i])?;
+                write!(writer, "\\u{:04x}", c as u32)?;
+                start = i + c.len_utf8();
+            }
+        }
+        writer.write_all(&fragment.as_bytes()[start..])
+    }
+}
+
+impl<T: Write> JsonLinesOutput<T> {
+    fn write_ret(&mut self, event: ExecEvent) -> Result<()> {
+        let ExecEvent {
+            exec: ret,
+            exit,
+            incomplete,
+        } = event;
+        let mut writer = self.opts.writer.lock().map_err(|_| Error::RunTimeError {
+            msg: "failed to write output",
         })?;
-        let args = args.remove(&ret.pid);
-        let args = args.map(|args| args.join(" ")).unwrap_or_else(|| "-".to_string());
 
-        if !self.opts.only_ancestor || ret.ancestor {
-            let json_line = JsonLine::from_ret_and_args(ret, args, self.opts.numeric);
-            let json_line = serde_json::to_string(&json_line)?;
+        if !self.opts.only_ancestor || ret.ancestor.is_some() {
+            let args = match (self.opts.legacy_args, ret.filename.is_empty() && ret.argv.is_empty()) {
+                (false, _) => None,
+                (true, true) => Some("-".to_string()),
+                (true, false) => Some(self.opts.encoding.encode(&ret.command_line())),
+            };
+            let time = Time::new(ret.timestamp, self.opts.time_format, self.start);
+            let mut json_line = JsonLine::from_ret_and_args(ret, args, time, self.opts.numeric, self.opts.encoding);
+            if let Some(exit) = exit {
+                json_line.event = "completed";
+                json_line.exit_time = Some(Time::new(exit.timestamp, self.opts.time_format, self.start));
+                json_line.exit = Some(exit.into());
+            } else if incomplete {
+                json_line.event = "incomplete";
+            }
+            let json_line = to_json(&json_line)?;
             writeln!(writer, "{}", json_line)?;
         }
 
@@ -91,29 +196,192 @@ impl<T: Write> Output for JsonLinesOutput<T> {
 
 #[derive(Debug, Serialize)]
 struct JsonLine {
+    event: &'static str,
+    time: Time,
     pid: u32,
+    /// Thread which called exec
+    tid: u32,
     ppid: u32,
-    ancestor: bool,
+    #[serde(flatten)]
+    ancestor: JsonAncestor,
+    /// Ancestors starting with the oldest one followed by the process itself
+    lineage: Vec<Ancestor>,
     comm: String,
     tty: String,
     uid: User,
     gid: Group,
     return_value: i32,
-    args: String,
+    syscall: Syscall,
+    empty_path: bool,
+    cwd: Option<String>,
+    /// Set if cwd has been read from procfs after the exec
+    cwd_from_procfs: bool,
+    /// Absolute path of the exec'ed file
+    path: Option<String>,
+    /// Filename followed by the arguments after argv[0], joined by spaces; cf. `JsonLinesOutputOpts::legacy_args`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    args: Option<String>,
+    filename: String,
+    argv: Vec<String>,
+    /// Number of recorded arguments
+    argc: usize,
+    /// Set if there have been more arguments than recorded
+    truncated: bool,
+    /// Set if argv[0] names a different program than filename
+    argv0_mismatch: bool,
+    /// Captured environment variables, cf. `EnvOpts`
+    env: BTreeMap<String, String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    exit_time: Option<Time>,
+    #[serde(flatten)]
+    exit: Option<JsonExitStatus>,
 }
 
 impl JsonLine {
-    fn from_ret_and_args(ret: Return, args: String, numeric: bool) -> JsonLine {
+    fn from_ret_and_args(ret: Return, args: Option<String>, time: Time, numeric: bool, encoding: Encoding) -> JsonLine {
+        let argv0_mismatch = ret.argv0_mismatch();
+        let mut lineage = ret.ancestors;
+        lineage.push(Ancestor {
+            pid: ret.pid,
+            comm: ret.comm.clone(),
+        });
         JsonLine {
+            event: "exec",
+            time,
             pid: ret.pid,
+            tid: ret.tid,
             ppid: ret.ppid,
-            ancestor: ret.ancestor,
+            ancestor: ret.ancestor.into(),
+            lineage,
             comm: ret.comm,
             tty: ret.tty,
             uid: ret.uid.to_user(numeric),
             gid: ret.gid.to_group(numeric),
             return_value: ret.ret_val,
+            syscall: ret.syscall,
+            empty_path: ret.empty_path,
+            cwd: ret.cwd.map(|x| encoding.encode(&x)),
+            cwd_from_procfs: ret.cwd_from_procfs,
+            path: ret.path.map(|x| encoding.encode(&x)),
             args,
+            argv0_mismatch,
+            filename: encoding.encode(&ret.filename),
+            argc: ret.argv.len(),
+            argv: ret.argv.iter().map(|x| encoding.encode(x)).collect(),
+            truncated: ret.truncated,
+            env: ret.env.into_iter().map(|(k, v)| (k, encoding.encode(&v))).collect(),
+            exit_time: None,
+            exit: None,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct JsonExit {
+    event: &'static str,
+    time: Time,
+    exec_time: Time,
+    pid: u32,
+    #[serde(flatten)]
+    ancestor: JsonAncestor,
+    comm: String,
+    #[serde(flatten)]
+    status: JsonExitStatus,
+}
+
+impl JsonExit {
+    fn new(exit: Exit, time_format: TimeFormat, start: SystemTime) -> JsonExit {
+        JsonExit {
+            event: "exit",
+            time: Time::new(exit.timestamp, time_format, start),
+            exec_time: Time::new(exit.exec_timestamp, time_format, start),
+            pid: exit.pid,
+            ancestor: exit.ancestor.clone().into(),
+            comm: exit.comm.clone(),
+            status: exit.into(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct JsonAncestor {
+    ancestor: bool,
+    ancestor_name: Option<String>,
+    /// Label of the matched ancestor name
+    origin: Option<String>,
+}
+
+impl From<Option<AncestorName>> for JsonAncestor {
+    fn from(ancestor: Option<AncestorName>) -> JsonAncestor {
+        match ancestor {
+            Some(ancestor_name) => JsonAncestor {
+                ancestor: true,
+                ancestor_name: Some(ancestor_name.name),
+                origin: ancestor_name.label,
+            },
+            None => JsonAncestor {
+                ancestor: false,
+                ancestor_name: None,
+                origin: None,
+            },
+        }
+    }
+}
+
+/// Durations in seconds
+#[derive(Debug, Serialize)]
+struct JsonExitStatus {
+    duration: f64,
+    exit_code: i32,
+    signal: i32,
+    user_time: f64,
+    system_time: f64,
+    max_rss_kb: u64,
+}
+
+impl From<Exit> for JsonExitStatus {
+    fn from(exit: Exit) -> JsonExitStatus {
+        JsonExitStatus {
+            duration: exit.duration().as_secs_f64(),
+            exit_code: exit.exit_code,
+            signal: exit.signal,
+            user_time: exit.user_time.as_secs_f64(),
+            system_time: exit.system_time.as_secs_f64(),
+            max_rss_kb: exit.max_rss_kb,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct JsonLost {
+    event: &'static str,
+    cpu: u32,
+    count: u64,
+}
+
+impl From<Lost> for JsonLost {
+    fn from(lost: Lost) -> JsonLost {
+        JsonLost {
+            event: "lost",
+            cpu: lost.cpu,
+            count: lost.count,
+        }
+    }
+}
+
+/// Execs dropped by the kernel which could not be reported; execs whose return the kernel missed are reported with event
+/// "incomplete" instead
+#[derive(Debug, Serialize)]
+struct JsonDropped {
+    event: &'static str,
+    count: u64,
+}
+
+impl From<Dropped> for JsonDropped {
+    fn from(dropped: Dropped) -> JsonDropped {
+        JsonDropped {
+            event: "dropped",
+            count: dropped.count,
         }
     }
 }
@@ -13,21 +13,83 @@
 // limitations under the License.
 
 use serde::Serialize;
+use std::ffi::OsStr;
 use std::fmt;
+use std::os::unix::ffi::OsStrExt;
+use std::str::FromStr;
+use std::time::SystemTime;
 
 pub use json_lines::{JsonLinesOutput, JsonLinesOutputOpts};
 pub use table::{TableOutput, TableOutputOpts};
 
-use crate::Result;
-use crate::{Arg, Return};
+use crate::{Dropped, ExecEvent, Exit, Lost, Return};
+use crate::{Error, Result};
 
 mod json_lines;
 mod table;
 
+/// Receives the records assembled by `ExecAssembler`.
 pub trait Output {
     fn header(&mut self) -> Result<()>;
-    fn arg(&mut self, arg: Arg) -> Result<()>;
+    /// Receives the time of the first event before it is reported; relative times count from it.
+    fn start(&mut self, time: SystemTime) -> Result<()>;
+    /// Reports an exec together with the exit of the started process, if merged.
+    fn exec(&mut self, event: ExecEvent) -> Result<()>;
+    /// Reports the exit of a process whose exec has been reported before.
+    fn exit(&mut self, exit: Exit) -> Result<()>;
+    /// Reports events the kernel had to drop.
+    fn lost(&mut self, lost: Lost) -> Result<()>;
+    /// Reports execs the kernel dropped before they returned.
+    fn dropped(&mut self, dropped: Dropped) -> Result<()>;
+}
+
+/// Output of earlier versions which receives execs with and without exit separately, cf. `LegacyAdapter`
+pub trait LegacyOutput {
+    fn header(&mut self) -> Result<()>;
+    /// Receives the time of the first event before it is reported; relative times count from it.
+    fn start(&mut self, time: SystemTime) -> Result<()>;
     fn ret(&mut self, ret: Return) -> Result<()>;
+    /// Reports the exit of a process whose exec has been reported before.
+    fn exit(&mut self, exit: Exit) -> Result<()>;
+    /// Reports an exec together with the exit of the started process.
+    fn completed(&mut self, ret: Return, exit: Exit) -> Result<()>;
+    /// Reports events the kernel had to drop.
+    fn lost(&mut self, lost: Lost) -> Result<()>;
+    /// Reports execs the kernel dropped before they returned.
+    fn dropped(&mut self, dropped: Dropped) -> Result<()>;
+}
+
+/// Passes the records of `Output` to a `LegacyOutput`.
+#[derive(Debug)]
+pub struct LegacyAdapter<T: LegacyOutput>(pub T);
+
+impl<T: LegacyOutput> Output for LegacyAdapter<T> {
+    fn header(&mut self) -> Result<()> {
+        self.0.header()
+    }
+
+    fn start(&mut self, time: SystemTime) -> Result<()> {
+        self.0.start(time)
+    }
+
+    fn exec(&mut self, event: ExecEvent) -> Result<()> {
+        match event.exit {
+            Some(exit) => self.0.completed(event.exec, exit),
+            None => self.0.ret(event.exec),
+        }
+    }
+
+    fn exit(&mut self, exit: Exit) -> Result<()> {
+        self.0.exit(exit)
+    }
+
+    fn lost(&mut self, lost: Lost) -> Result<()> {
+        self.0.lost(lost)
+    }
+
+    fn dropped(&mut self, dropped: Dropped) -> Result<()> {
+        self.0.dropped(dropped)
+    }
 }
 
 #[derive(Debug, Clone, Serialize)]
@@ -43,14 +105,14 @@ impl User {
     }
 
     pub fn from_id(id: u32) -> User {
-        Self::try_from_id(id).unwrap_or_else(|| User::Id(id))
+        Self::try_from_id(id).unwrap_or(User::Id(id))
     }
 }
 
 impl fmt::Display for User {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            User::Name(name) => f.write_str(&name),
+            User::Name(name) => f.write_str(name),
             User::Id(id) => f.write_fmt(format_args!("{}", id)),
         }
     }
@@ -69,14 +131,14 @@ impl Group {
     }
 
     pub fn from_id(id: u32) -> Group {
-        Self::try_from_id(id).unwrap_or_else(|| Group::Id(id))
+        Self::try_from_id(id).unwrap_or(Group::Id(id))
     }
 }
 
 impl fmt::Display for Group {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            Group::Name(name) => f.write_str(&name),
+            Group::Name(name) => f.write_str(name),
             Group::Id(id) => f.write_fmt(format_args!("{}", id)),
         }
     }
@@ -104,3 +166,229 @@ impl ToName for u32 {
         }
     }
 }
+
+/// Format of timestamps in outputs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeFormat {
+    /// RFC 3339 in UTC with millisecond precision
+    Rfc3339,
+    /// Milliseconds since the epoch
+    EpochMillis,
+    /// Seconds since the first event
+    Relative,
+}
+
+impl TimeFormat {
+    pub const ALL: [TimeFormat; 3] = [TimeFormat::Rfc3339, TimeFormat::EpochMillis, TimeFormat::Relative];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            TimeFormat::Rfc3339 => "rfc3339",
+            TimeFormat::EpochMillis => "epoch-millis",
+            TimeFormat::Relative => "relative",
+        }
+    }
+
+    /// Width of a formatted timestamp for tabular output.
+    pub fn width(&self) -> usize {
+        match self {
+            TimeFormat::Rfc3339 => 24,
+            TimeFormat::EpochMillis => 13,
+            TimeFormat::Relative => 10,
+        }
+    }
+}
+
+impl FromStr for TimeFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        TimeFormat::ALL
+            .iter()
+            .find(|x| x.name() == s.to_lowercase())
+            .copied()
+            .ok_or_else(|| Error::InvalidTimeFormat { name: s.to_string() })
+    }
+}
+
+/// Encoding of filenames, arguments, paths, and environment variables, which may contain arbitrary bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    /// UTF-8 with invalid bytes as `\xNN` and backslashes as `\\`, so the original bytes can be restored
+    Escaped,
+    /// Base64 of the original bytes
+    Base64,
+    /// UTF-8 with invalid bytes replaced by U+FFFD
+    Lossy,
+}
+
+impl Encoding {
+    pub const ALL: [Encoding; 3] = [Encoding::Escaped, Encoding::Base64, Encoding::Lossy];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Encoding::Escaped => "escaped",
+            Encoding::Base64 => "base64",
+            Encoding::Lossy => "lossy",
+        }
+    }
+
+    pub fn encode(&self, s: &OsStr) -> String {
+        let bytes = s.as_bytes();
+        match self {
+            Encoding::Escaped => {
+                let mut escaped = String::with_capacity(bytes.len());
+                for chunk in bytes.utf8_chunks() {
+                    escaped.push_str(&chunk.valid().replace('\\', "\\\\"));
+                    for b in chunk.invalid() {
+                        escaped.push_str(&format!("\\x{:02x}", b));
+                    }
+                }
+                escaped
+            }
+            Encoding::Base64 => base64::encode(bytes),
+            Encoding::Lossy => String::from_utf8_lossy(bytes).to_string(),
+        }
+    }
+}
+
+impl FromStr for Encoding {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Encoding::ALL
+            .iter()
+            .find(|x| x.name() == s.to_lowercase())
+            .copied()
+            .ok_or_else(|| Error::InvalidEncoding { name: s.to_string() })
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(untagged)]
+pub enum Time {
+    Rfc3339(String),
+    EpochMillis(u64),
+    Relative(f64),
+}
+
+impl Time {
+    pub fn new(time: SystemTime, format: TimeFormat, start: SystemTime) -> Time {
+        match format {
+            TimeFormat::Rfc3339 => Time::Rfc3339(humantime::format_rfc3339_millis(time).to_string()),
+            TimeFormat::EpochMillis => Time::EpochMillis(
+                time.duration_since(SystemTime::UNIX_EPOCH)
+                    .map(|x| x.as_millis() as u64)
+                    .unwrap_or(0),
+            ),
+            TimeFormat::Relative => Time::Relative(match time.duration_since(start) {
+                Ok(since) => since.as_secs_f64(),
+                Err(before) => -before.duration().as_secs_f64(),
+            }),
+        }
+    }
+}
+
+impl fmt::Display for Time {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Time::Rfc3339(time) => f.pad(time),
+            Time::EpochMillis(millis) => f.pad(&millis.to_string()),
+            Time::Relative(secs) => f.pad(&format!("{:.3}", secs)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::OsString;
+    use std::os::unix::ffi::OsStringExt;
+
+    /// Restores the bytes of `Encoding::Escaped`.
+    fn unescape(s: &str) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        let mut rest = s.as_bytes();
+        while let Some((&b, tail)) = rest.split_first() {
+            match (b, tail) {
+                (b'\\', [b'\\', tail @ ..]) => {
+                    bytes.push(b'\\');
+                    rest = tail;
+                }
+                (b'\\', [b'x', h, l, tail @ ..]) => {
+                    let hex = std::str::from_utf8(&[*h, *l]).unwrap().to_string();
+                    bytes.push(u8::from_str_radix(&hex, 16).unwrap());
+                    rest = tail;
+                }
+                (b'\\', _) => panic!("unescaped backslash in {:?}", s),
+                _ => {
+                    bytes.push(b);
+                    rest = tail;
+                }
+            }
+        }
+        bytes
+    }
+
+    fn samples() -> Vec<Vec<u8>> {
+        vec![
+            b"".to_vec(),
+            b"ls".to_vec(),
+            // Literal escape sequence vs. the byte it would stand for
+            b"\\x41".to_vec(),
+            b"A".to_vec(),
+            b"\\".to_vec(),
+            b"\\\\x".to_vec(),
+            b"caf\xc3\xa9".to_vec(),
+            b"\xff\xfe".to_vec(),
+            // Truncated multi-byte sequence followed by a backslash
+            b"\xc3\\x".to_vec(),
+            b"\x1b[2J".to_vec(),
+        ]
+    }
+
+    #[test]
+    fn escaped_round_trips() {
+        for bytes in samples() {
+            let encoded = Encoding::Escaped.encode(&OsString::from_vec(bytes.clone()));
+            assert_eq!(unescape(&encoded), bytes, "{:?}", encoded);
+        }
+    }
+
+    #[test]
+    fn escaped_distinguishes_literal_escapes_from_raw_bytes() {
+        let encode = |x: &[u8]| Encoding::Escaped.encode(OsStr::from_bytes(x));
+
+        assert_eq!(encode(b"\\x41"), "\\\\x41");
+        assert_eq!(encode(b"A"), "A");
+        assert_eq!(encode(b"\x81"), "\\x81");
+        assert_eq!(encode(b"\\x81"), "\\\\x81");
+        assert_eq!(encode("café".as_bytes()), "café");
+        assert_eq!(encode(b"a\xffb"), "a\\xffb");
+    }
+
+    #[test]
+    fn base64_round_trips() {
+        for bytes in samples() {
+            let encoded = Encoding::Base64.encode(&OsString::from_vec(bytes.clone()));
+            assert_eq!(base64::decode(&encoded).unwrap(), bytes);
+        }
+    }
+
+    #[test]
+    fn lossy_replaces_invalid_bytes() {
+        let encode = |x: &[u8]| Encoding::Lossy.encode(OsStr::from_bytes(x));
+
+        assert_eq!(encode("café\\x41".as_bytes()), "café\\x41");
+        assert_eq!(encode(b"a\xffb"), "a\u{fffd}b");
+    }
+
+    #[test]
+    fn parse_encoding() {
+        for encoding in Encoding::ALL.iter() {
+            assert_eq!(encoding.name().parse::<Encoding>().unwrap(), *encoding);
+        }
+        assert_eq!("Base64".parse::<Encoding>().unwrap(), Encoding::Base64);
+        assert!(matches!("hex".parse::<Encoding>(), Err(Error::InvalidEncoding { .. })));
+    }
+}
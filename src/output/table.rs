@@ -12,12 +12,15 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::collections::HashMap;
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::ffi::OsString;
 use std::io::Write;
 use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
 
-use crate::output::{Output, ToName};
-use crate::{Arg, Return};
+use crate::output::{Encoding, Output, Time, TimeFormat, ToName};
+use crate::{AncestorName, Dropped, ExecEvent, Exit, Lost, Return};
 use crate::{Error, Result};
 
 #[derive(Debug)]
@@ -25,6 +28,11 @@ pub struct TableOutputOpts<T: Write> {
     writer: Arc<Mutex<T>>,
     only_ancestor: bool,
     numeric: bool,
+    time_format: TimeFormat,
+    env_columns: Vec<String>,
+    shell_quote: bool,
+    encoding: Encoding,
+    escape_control: bool,
 }
 
 impl<T: Write> TableOutputOpts<T> {
@@ -33,23 +41,133 @@ impl<T: Write> TableOutputOpts<T> {
             writer: Arc::new(Mutex::new(writer)),
             only_ancestor,
             numeric,
+            time_format: TimeFormat::Rfc3339,
+            env_columns: Vec::new(),
+            shell_quote: false,
+            encoding: Encoding::Escaped,
+            escape_control: true,
         }
     }
+
+    pub fn time_format(mut self, time_format: TimeFormat) -> TableOutputOpts<T> {
+        self.time_format = time_format;
+        self
+    }
+
+    /// Sets the encoding of filename, arguments, paths, and environment variables
+    pub fn encoding(mut self, encoding: Encoding) -> TableOutputOpts<T> {
+        self.encoding = encoding;
+        self
+    }
+
+    /// Escapes control characters in all fields, so executed commands cannot rewrite the terminal by escape
+    /// sequences; enabled by default
+    pub fn escape_control(mut self, escape_control: bool) -> TableOutputOpts<T> {
+        self.escape_control = escape_control;
+        self
+    }
+
+    /// Quotes filename and arguments like a shell would need them, so argument boundaries are kept
+    pub fn shell_quote(mut self, shell_quote: bool) -> TableOutputOpts<T> {
+        self.shell_quote = shell_quote;
+        self
+    }
+
+    /// Adds a column per environment variable before the arguments; the variables must be captured, cf. `EnvOpts`
+    pub fn env_columns(mut self, env_columns: Vec<String>) -> TableOutputOpts<T> {
+        self.env_columns = env_columns;
+        self
+    }
 }
 
 #[derive(Debug)]
 pub struct TableOutput<T: Write> {
-    args: Arc<Mutex<HashMap<u32, Vec<String>>>>,
     opts: TableOutputOpts<T>,
+    start: SystemTime,
 }
 
 impl<T: Write> TableOutput<T> {
     pub fn new(opts: TableOutputOpts<T>) -> Self {
         TableOutput {
-            args: Arc::new(Mutex::new(HashMap::new())),
             opts,
+            start: SystemTime::UNIX_EPOCH,
         }
     }
+
+    /// Cells of the environment columns each followed by a space; `None` fills the cells with the column names
+    fn env_cells(&self, env: Option<&BTreeMap<String, OsString>>) -> String {
+        self.opts
+            .env_columns
+            .iter()
+            .map(|name| {
+                let value = match env {
+                    Some(env) => env
+                        .get(name)
+                        .map(|x| self.opts.encoding.encode(x))
+                        .unwrap_or_else(|| "-".to_string()),
+                    None => name.clone(),
+                };
+                format!("{:-width$} ", value, width = name.len().max(16))
+            })
+            .collect()
+    }
+
+    fn escape<'a>(&self, line: &'a str) -> Cow<'a, str> {
+        if self.opts.escape_control {
+            escape_control(line)
+        } else {
+            Cow::Borrowed(line)
+        }
+    }
+
+    fn write_ret(&mut self, event: ExecEvent) -> Result<()> {
+        let ExecEvent {
+            exec: ret,
+            exit,
+            incomplete,
+        } = event;
+        let mut writer = self.opts.writer.lock().map_err(|_| Error::RunTimeError {
+            msg: "failed to write output",
+        })?;
+
+        if !self.opts.only_ancestor || ret.ancestor.is_some() {
+            let mut args = if ret.filename.is_empty() && ret.argv.is_empty() {
+                "-".to_string()
+            } else if self.opts.shell_quote {
+                quoted_command_line(&ret, self.opts.encoding)
+            } else {
+                self.opts.encoding.encode(&ret.command_line())
+            };
+            if let Some(exit) = exit {
+                args = format!("{} {}", args, exit_summary(&exit));
+            } else if incomplete {
+                args = format!("{} [incomplete]", args);
+            }
+            let env = self.env_cells(Some(&ret.env));
+            let line = format!(
+                "{:-width$} {:-16} {:-<6} {:-<6} {:-<6} {:-<6} {:-<6} {:-16} {:-6} {:-32} {:-24} {}{}",
+                Time::new(ret.timestamp, self.opts.time_format, self.start),
+                ret.comm,
+                ret.pid,
+                ret.ppid,
+                ret.uid.to_user(self.opts.numeric),
+                ret.gid.to_group(self.opts.numeric),
+                ret.ret_val,
+                ancestor(&ret.ancestor),
+                ret.tty,
+                ret.lineage(),
+                ret.cwd
+                    .map(|x| self.opts.encoding.encode(&x))
+                    .unwrap_or_else(|| "-".to_string()),
+                env,
+                args,
+                width = self.opts.time_format.width()
+            );
+            writeln!(writer, "{}", self.escape(&line))?;
+        }
+
+        Ok(())
+    }
 }
 
 impl<T: Write> Output for TableOutput<T> {
@@ -57,52 +175,250 @@ impl<T: Write> Output for TableOutput<T> {
         let mut writer = self.opts.writer.lock().map_err(|_| Error::RunTimeError {
             msg: "failed to write output",
         })?;
-        writeln!(
-            writer,
-            "{:-16} {:-6} {:-6} {:-6} {:-6} {:-6} {:-9} {:-6} Args",
-            "PCOMM", "PID", "PPID", "UID", "GID", "RET", "ANCESTOR?", "TTY"
-        )?;
+        let line = format!(
+            "{:-width$} {:-16} {:-6} {:-6} {:-6} {:-6} {:-6} {:-16} {:-6} {:-32} {:-24} {}Args",
+            "TIME",
+            "PCOMM",
+            "PID",
+            "PPID",
+            "UID",
+            "GID",
+            "RET",
+            "ANCESTOR",
+            "TTY",
+            "LINEAGE",
+            "CWD",
+            self.env_cells(None),
+            width = self.opts.time_format.width()
+        );
+        writeln!(writer, "{}", self.escape(&line))?;
+
+        Ok(())
+    }
 
+    fn start(&mut self, time: SystemTime) -> Result<()> {
+        self.start = time;
         Ok(())
     }
 
-    fn arg(&mut self, arg: Arg) -> Result<()> {
-        let mut args = self.args.lock().map_err(|_| Error::RunTimeError {
-            msg: "failed to collect for output",
+    fn exec(&mut self, event: ExecEvent) -> Result<()> {
+        self.write_ret(event)
+    }
+
+    fn exit(&mut self, exit: Exit) -> Result<()> {
+        let mut writer = self.opts.writer.lock().map_err(|_| Error::RunTimeError {
+            msg: "failed to write output",
         })?;
-        let value = args.entry(arg.pid).or_insert_with(Vec::new);
-        value.push(arg.argv);
+
+        if !self.opts.only_ancestor || exit.ancestor.is_some() {
+            let line = format!(
+                "{:-width$} {:-16} {:-<6} {:-6} {:-6} {:-6} {:-6} {:-16} {:-6} {:-32} {:-24} {}{}",
+                Time::new(exit.timestamp, self.opts.time_format, self.start),
+                exit.comm,
+                exit.pid,
+                "-",
+                "-",
+                "-",
+                "-",
+                ancestor(&exit.ancestor),
+                "-",
+                "-",
+                "-",
+                self.env_cells(Some(&BTreeMap::new())),
+                exit_summary(&exit),
+                width = self.opts.time_format.width()
+            );
+            writeln!(writer, "{}", self.escape(&line))?;
+        }
 
         Ok(())
     }
 
-    fn ret(&mut self, ret: Return) -> Result<()> {
+    fn lost(&mut self, lost: Lost) -> Result<()> {
         let mut writer = self.opts.writer.lock().map_err(|_| Error::RunTimeError {
             msg: "failed to write output",
         })?;
+        writeln!(writer, "*** lost {} events on CPU {} ***", lost.count, lost.cpu)?;
 
-        let mut args = self.args.lock().map_err(|_| Error::RunTimeError {
-            msg: "failed to collect for output",
+        Ok(())
+    }
+
+    fn dropped(&mut self, dropped: Dropped) -> Result<()> {
+        let mut writer = self.opts.writer.lock().map_err(|_| Error::RunTimeError {
+            msg: "failed to write output",
         })?;
-        let args = args.remove(&ret.pid);
-        let args = args.map(|args| args.join(" ")).unwrap_or_else(|| "-".to_string());
+        writeln!(writer, "*** dropped {} incomplete execs ***", dropped.count)?;
 
-        if !self.opts.only_ancestor || ret.ancestor {
-            writeln!(
-                writer,
-                "{:-16} {:-<6} {:-<6} {:-<6} {:-<6} {:-<6} {:-9} {:-6} {}",
-                ret.comm,
-                ret.pid,
-                ret.ppid,
-                ret.uid.to_user(self.opts.numeric),
-                ret.gid.to_group(self.opts.numeric),
-                ret.ret_val,
-                ret.ancestor,
-                ret.tty,
-                args
-            )?;
+        Ok(())
+    }
+}
+
+fn exit_summary(exit: &Exit) -> String {
+    let status = if exit.signal != 0 {
+        format!("signal={}", exit.signal)
+    } else {
+        format!("exit={}", exit.exit_code)
+    };
+    format!(
+        "[{} duration={:.3}s user={:.3}s sys={:.3}s maxrss={}KiB]",
+        status,
+        exit.duration().as_secs_f64(),
+        exit.user_time.as_secs_f64(),
+        exit.system_time.as_secs_f64(),
+        exit.max_rss_kb
+    )
+}
+
+/// Filename and arguments after argv[0] quoted for a POSIX shell followed by "..." if truncated; an argument "..." is
+/// quoted to tell it apart
+fn quoted_command_line(ret: &Return, encoding: Encoding) -> String {
+    let mut args: Vec<_> = std::iter::once(&ret.filename)
+        .chain(ret.argv.iter().skip(1))
+        .map(|x| shell_quote(&encoding.encode(x)).into_owned())
+        .collect();
+    if ret.truncated {
+        args.push("...".into());
+    }
+    args.join(" ")
+}
+
+/// Replaces control characters including ESC by `\xNN`; all of them are at most U+009F.
+fn escape_control(line: &str) -> Cow<'_, str> {
+    if !line.chars().any(char::is_control) {
+        return Cow::Borrowed(line);
+    }
+    let mut escaped = String::with_capacity(line.len());
+    for c in line.chars() {
+        if c.is_control() {
+            escaped.push_str(&format!("\\x{:02x}", c as u32));
+        } else {
+            escaped.push(c);
         }
+    }
+    Cow::Owned(escaped)
+}
 
-        Ok(())
+fn shell_quote(arg: &str) -> Cow<'_, str> {
+    let safe = |c: char| c.is_ascii_alphanumeric() || "_@%+=:,./-".contains(c);
+    if !arg.is_empty() && arg != "..." && arg.chars().all(safe) {
+        Cow::Borrowed(arg)
+    } else {
+        Cow::Owned(format!("'{}'", arg.replace('\'', "'\\''")))
+    }
+}
+
+/// Label of the matched ancestor name or the name itself
+fn ancestor(ancestor: &Option<AncestorName>) -> &str {
+    match ancestor {
+        Some(AncestorName { label: Some(label), .. }) => label,
+        Some(ancestor_name) => &ancestor_name.name,
+        None => "-",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exec_logger::Syscall;
+    use std::os::unix::ffi::OsStringExt;
+    use std::process::Command;
+
+    fn ret(filename: &str, argv: &[&str], truncated: bool) -> Return {
+        Return {
+            timestamp: SystemTime::UNIX_EPOCH,
+            pid: 42,
+            tid: 42,
+            ppid: 1,
+            ancestor: None,
+            ancestors: Vec::new(),
+            comm: "ls".to_string(),
+            tty: String::new(),
+            uid: 0,
+            gid: 0,
+            ret_val: 0,
+            syscall: Syscall::Execve,
+            empty_path: false,
+            cwd: None,
+            cwd_from_procfs: false,
+            path: None,
+            filename: filename.into(),
+            argv: argv.iter().map(OsString::from).collect(),
+            truncated,
+            env: BTreeMap::new(),
+        }
+    }
+
+    #[test]
+    fn shell_quote_leaves_safe_args() {
+        assert_eq!(shell_quote("/usr/bin/ls"), "/usr/bin/ls");
+        assert_eq!(shell_quote("--color=auto"), "--color=auto");
+        assert_eq!(shell_quote("a,b:c@d%e+f_g"), "a,b:c@d%e+f_g");
+    }
+
+    #[test]
+    fn shell_quote_quotes_unsafe_args() {
+        assert_eq!(shell_quote(""), "''");
+        assert_eq!(shell_quote("a b"), "'a b'");
+        assert_eq!(shell_quote("$HOME"), "'$HOME'");
+        assert_eq!(shell_quote("it's"), "'it'\\''s'");
+        assert_eq!(shell_quote("a\\b"), "'a\\b'");
+        assert_eq!(shell_quote("*"), "'*'");
+    }
+
+    #[test]
+    fn shell_quote_round_trips_through_sh() {
+        let args = [
+            "",
+            "a b",
+            "it's",
+            "''",
+            "$(id)",
+            "`id`",
+            "a\\b",
+            "*",
+            "\"x\"",
+            "tab\there",
+            "new\nline",
+            "-n",
+        ];
+        let script = std::iter::once("printf '%s\\0'".to_string())
+            .chain(args.iter().map(|x| shell_quote(x).into_owned()))
+            .collect::<Vec<_>>()
+            .join(" ");
+
+        let output = Command::new("sh").arg("-c").arg(&script).output().unwrap();
+
+        let expected: Vec<u8> = args.iter().flat_map(|x| x.bytes().chain(std::iter::once(0))).collect();
+        assert_eq!(output.stdout, expected);
+    }
+
+    #[test]
+    fn quoted_command_line_skips_argv0() {
+        let ret = ret("/bin/echo", &["echo", "hello world", "it's"], false);
+
+        assert_eq!(
+            quoted_command_line(&ret, Encoding::Escaped),
+            "/bin/echo 'hello world' 'it'\\''s'"
+        );
+    }
+
+    #[test]
+    fn quoted_command_line_marks_truncation_unquoted() {
+        let literal = ret("/bin/echo", &["echo", "..."], false);
+        let truncated = ret("/bin/echo", &["echo"], true);
+
+        assert_eq!(quoted_command_line(&literal, Encoding::Escaped), "/bin/echo '...'");
+        assert_eq!(quoted_command_line(&truncated, Encoding::Escaped), "/bin/echo ...");
+        let both = ret("/bin/echo", &["echo", "..."], true);
+        assert_eq!(quoted_command_line(&both, Encoding::Escaped), "/bin/echo '...' ...");
+    }
+
+    #[test]
+    fn quoted_command_line_quotes_escaped_bytes() {
+        let mut ret = ret("/bin/echo", &["echo"], false);
+        ret.argv.push(OsString::from_vec(b"a\xffb".to_vec()));
+
+        assert_eq!(quoted_command_line(&ret, Encoding::Escaped), "/bin/echo 'a\\xffb'");
+        assert_eq!(quoted_command_line(&ret, Encoding::Base64), "L2Jpbi9lY2hv Yf9i");
     }
 }
@@ -0,0 +1,287 @@
+// Copyright 2020 Lukas Pustina
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Sources of the events `ExecLogger` turns into output.
+//!
+//! `KProbeSource` receives events from the BPF program and requires root; `ReplaySource` delivers given events, e.g.,
+//! to run the logger and its outputs in tests; `CaptureSource` replays a capture file, cf. `crate::capture`.
+
+use crate::bpf::{self, AncestorIds, Event, Exec, Filters, KProbeOpts, LostEvents};
+use crate::capture::{CaptureReader, ReplaySpeed};
+use crate::Result;
+use log::debug;
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+/// Receives the events of a source.
+pub type EventHandler = Arc<dyn Fn(Event) + Send + Sync>;
+/// Receives the numbers of events a source lost.
+pub type LostHandler = Arc<dyn Fn(LostEvents) + Send + Sync>;
+
+/// Everything `ExecLogger` passes on to the source it runs.
+pub struct SourceContext {
+    /// Cleared when the logger is stopped
+    pub runnable: Arc<AtomicBool>,
+    pub opts: KProbeOpts,
+    /// Filters changed while running, cf. `RunningExecLogger::filter_updater`
+    pub filter_updates: mpsc::Receiver<Filters>,
+    pub ancestor_ids: AncestorIds,
+}
+
+/// Delivers events to an `ExecLogger`.
+pub trait EventSource: fmt::Debug + Send {
+    /// Runs on the logging thread until the source is exhausted or `context.runnable` is cleared.
+    fn run(self: Box<Self>, context: SourceContext, handler: EventHandler, lost_handler: LostHandler) -> Result<()>;
+
+    /// Whether events are delivered while they happen, so their processes may still be inspected in procfs
+    fn live(&self) -> bool {
+        false
+    }
+}
+
+/// Receives events from the BPF program; this is the default source.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct KProbeSource;
+
+impl EventSource for KProbeSource {
+    fn run(self: Box<Self>, context: SourceContext, handler: EventHandler, lost_handler: LostHandler) -> Result<()> {
+        let kprobe = bpf::KProbe::new(
+            context.runnable,
+            move |event| handler(event),
+            move |lost| lost_handler(lost),
+            context.opts,
+            context.filter_updates,
+            context.ancestor_ids,
+        );
+        kprobe.run()
+    }
+
+    fn live(&self) -> bool {
+        true
+    }
+}
+
+/// Event or lost events to replay.
+// Items are moved into the handlers, so the size difference does not matter.
+#[allow(clippy::large_enum_variant)]
+pub enum ReplayItem {
+    Event(Event),
+    Lost(LostEvents),
+}
+
+impl From<Event> for ReplayItem {
+    fn from(event: Event) -> Self {
+        ReplayItem::Event(event)
+    }
+}
+
+impl From<LostEvents> for ReplayItem {
+    fn from(lost: LostEvents) -> Self {
+        ReplayItem::Lost(lost)
+    }
+}
+
+/// Delivers given events in order and stops afterwards.
+///
+/// The ids in `bpf::Exec::ancestor` and `bpf::Exit::ancestor` refer to the ancestor names of
+/// `ExecLoggerOpts::filters` starting with 1, just like the BPF program reports them. Filters are not applied.
+#[derive(Default)]
+pub struct ReplaySource {
+    items: Vec<ReplayItem>,
+}
+
+impl ReplaySource {
+    pub fn new<T: Into<ReplayItem>, I: IntoIterator<Item = T>>(items: I) -> ReplaySource {
+        ReplaySource {
+            items: items.into_iter().map(Into::into).collect(),
+        }
+    }
+
+    /// Creates a source from samples as submitted by the BPF program; samples that cannot be decoded are reported as
+    /// malformed.
+    pub fn from_samples<T: AsRef<[u8]>, I: IntoIterator<Item = T>>(samples: I) -> ReplaySource {
+        ReplaySource::new(samples.into_iter().map(|x| Event::from_sample(x.as_ref())))
+    }
+}
+
+impl fmt::Debug for ReplaySource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ReplaySource")
+            .field("items", &self.items.len())
+            .finish()
+    }
+}
+
+impl EventSource for ReplaySource {
+    fn run(self: Box<Self>, context: SourceContext, handler: EventHandler, lost_handler: LostHandler) -> Result<()> {
+        for ancestor_name in &context.opts.filters.ancestor_names {
+            context.ancestor_ids.id(ancestor_name)?;
+        }
+        for item in self.items {
+            if !context.runnable.load(Ordering::SeqCst) {
+                break;
+            }
+            match item {
+                ReplayItem::Event(event) => handler(event),
+                ReplayItem::Lost(lost) => lost_handler(lost),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Replays a capture file.
+///
+/// The filters of `ExecLoggerOpts` and their updates are applied like the BPF program does, and ancestors are matched
+/// against the recorded ancestors. So a capture can be analyzed with different filters than it has been recorded with.
+#[derive(Debug)]
+pub struct CaptureSource {
+    reader: CaptureReader,
+    speed: ReplaySpeed,
+}
+
+impl CaptureSource {
+    pub fn new(reader: CaptureReader, speed: ReplaySpeed) -> CaptureSource {
+        CaptureSource { reader, speed }
+    }
+
+    pub fn open<P: AsRef<Path>>(path: P, speed: ReplaySpeed) -> Result<CaptureSource> {
+        Ok(CaptureSource::new(CaptureReader::open(path)?, speed))
+    }
+}
+
+impl EventSource for CaptureSource {
+    fn run(self: Box<Self>, context: SourceContext, handler: EventHandler, lost_handler: LostHandler) -> Result<()> {
+        let SourceContext {
+            runnable,
+            opts,
+            filter_updates,
+            ancestor_ids,
+        } = context;
+        let mut filters = opts.filters;
+        let mut clock = ReplayClock::new(self.speed, opts.interval_ms);
+        // Ancestor ids of execs passing the filters; exits are only reported for those, cf. `exec_starts`
+        let mut started: HashMap<u32, u32> = HashMap::new();
+
+        for record in self.reader {
+            let record = record?;
+            if !clock.wait_until(record.time, &runnable) {
+                break;
+            }
+            for updated in filter_updates.try_iter() {
+                debug!("Applying filter update to replay: {:?}", updated);
+                filters = updated;
+            }
+            match record.item {
+                ReplayItem::Event(Event::Exec(mut exec)) => {
+                    if !filter_exec(&mut exec, &filters, &ancestor_ids, opts.max_args)? {
+                        continue;
+                    }
+                    if exec.ret_val == 0 {
+                        started.insert(exec.pid, exec.ancestor);
+                    }
+                    handler(Event::Exec(exec))
+                }
+                ReplayItem::Event(Event::Incomplete(mut exec)) => {
+                    if filter_exec(&mut exec, &filters, &ancestor_ids, opts.max_args)? {
+                        handler(Event::Incomplete(exec))
+                    }
+                }
+                ReplayItem::Event(Event::Exit(mut exit)) => {
+                    if let Some(ancestor) = started.remove(&exit.pid) {
+                        exit.ancestor = ancestor;
+                        handler(Event::Exit(exit))
+                    }
+                }
+                ReplayItem::Event(event) => handler(event),
+                ReplayItem::Lost(lost) => lost_handler(lost),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Matches the ancestors of `exec` and applies `filters` like the BPF program does; returns whether `exec` passes.
+fn filter_exec(exec: &mut Exec, filters: &Filters, ancestor_ids: &AncestorIds, max_args: u32) -> Result<bool> {
+    exec.ancestor = exec
+        .ancestors
+        .iter()
+        .find_map(|x| filters.match_ancestor(&x.comm))
+        .map(|x| ancestor_ids.id(x))
+        .transpose()?
+        .unwrap_or(0);
+    if !filters.matches(exec) {
+        return Ok(false);
+    }
+    limit_args(exec, filters.max_args.unwrap_or(max_args).min(max_args));
+
+    Ok(true)
+}
+
+/// Keeps the filename and at most `max_args` arguments like the BPF program does.
+fn limit_args(exec: &mut Exec, max_args: u32) {
+    let end = exec
+        .args
+        .iter()
+        .enumerate()
+        .filter(|(_, &x)| x == 0)
+        .map(|(i, _)| i + 1)
+        .nth(max_args as usize);
+    if let Some(end) = end {
+        if end < exec.args.len() {
+            exec.args.truncate(end);
+            exec.args_truncated = true;
+        }
+    }
+}
+
+/// Delays records by the time between their recording.
+struct ReplayClock {
+    speed: ReplaySpeed,
+    interval: Duration,
+    start: Option<(Instant, SystemTime)>,
+}
+
+impl ReplayClock {
+    fn new(speed: ReplaySpeed, interval_ms: u32) -> ReplayClock {
+        ReplayClock {
+            speed,
+            interval: Duration::from_millis(interval_ms as u64),
+            start: None,
+        }
+    }
+
+    /// Waits until `time` relative to the first record; returns false if stopped while waiting.
+    fn wait_until(&mut self, time: SystemTime, runnable: &AtomicBool) -> bool {
+        if self.speed == ReplaySpeed::Original {
+            let (start, first) = *self.start.get_or_insert((Instant::now(), time));
+            let due = start + time.duration_since(first).unwrap_or_default();
+            loop {
+                let now = Instant::now();
+                if now >= due || !runnable.load(Ordering::SeqCst) {
+                    break;
+                }
+                thread::sleep((due - now).min(self.interval));
+            }
+        }
+        runnable.load(Ordering::SeqCst)
+    }
+}
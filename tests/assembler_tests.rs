@@ -0,0 +1,247 @@
+use common::{exec, exit};
+use exec_logger::bpf::{AncestorIds, DroppedExecs, Event};
+use exec_logger::env::EnvOpts;
+use exec_logger::output::{LegacyAdapter, LegacyOutput, Output};
+use exec_logger::{Dropped, ExecAssembler, ExecEvent, Exit, ExitMode, Lost, Result, Return};
+use std::time::{Duration, SystemTime};
+
+mod common;
+
+/// Records what an output receives as `(kind, pid, argv)`
+#[derive(Debug, Default)]
+struct Recorder(Vec<(&'static str, u32, Vec<String>)>);
+
+fn argv(ret: &Return) -> Vec<String> {
+    ret.argv.iter().map(|x| x.to_string_lossy().into_owned()).collect()
+}
+
+impl Output for Recorder {
+    fn header(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn start(&mut self, _: SystemTime) -> Result<()> {
+        Ok(())
+    }
+
+    fn exec(&mut self, event: ExecEvent) -> Result<()> {
+        let kind = match (&event.exit, event.incomplete) {
+            (Some(_), _) => "completed",
+            (None, true) => "incomplete",
+            (None, false) => "exec",
+        };
+        self.0.push((kind, event.exec.pid, argv(&event.exec)));
+        Ok(())
+    }
+
+    fn exit(&mut self, exit: Exit) -> Result<()> {
+        self.0.push(("exit", exit.pid, Vec::new()));
+        Ok(())
+    }
+
+    fn lost(&mut self, _: Lost) -> Result<()> {
+        Ok(())
+    }
+
+    fn dropped(&mut self, dropped: Dropped) -> Result<()> {
+        self.0.push(("dropped", dropped.count as u32, Vec::new()));
+        Ok(())
+    }
+}
+
+impl LegacyOutput for Recorder {
+    fn header(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn start(&mut self, _: SystemTime) -> Result<()> {
+        Ok(())
+    }
+
+    fn ret(&mut self, ret: Return) -> Result<()> {
+        self.0.push(("ret", ret.pid, argv(&ret)));
+        Ok(())
+    }
+
+    fn exit(&mut self, exit: Exit) -> Result<()> {
+        self.0.push(("exit", exit.pid, Vec::new()));
+        Ok(())
+    }
+
+    fn completed(&mut self, ret: Return, _: Exit) -> Result<()> {
+        self.0.push(("completed", ret.pid, argv(&ret)));
+        Ok(())
+    }
+
+    fn lost(&mut self, _: Lost) -> Result<()> {
+        Ok(())
+    }
+
+    fn dropped(&mut self, dropped: Dropped) -> Result<()> {
+        self.0.push(("dropped", dropped.count as u32, Vec::new()));
+        Ok(())
+    }
+}
+
+fn assembler(exits: Option<ExitMode>) -> ExecAssembler {
+    ExecAssembler::new(AncestorIds::default(), EnvOpts::default(), exits)
+}
+
+fn assemble<O: Output>(exits: Option<ExitMode>, events: Vec<Event>, output: &mut O) {
+    assemble_with(assembler(exits), events, output);
+}
+
+fn assemble_with<O: Output>(mut assembler: ExecAssembler, events: Vec<Event>, output: &mut O) {
+    for event in events {
+        assembler.push(event, output).unwrap();
+    }
+    assembler.finish(output).unwrap();
+}
+
+fn strings(xs: &[&str]) -> Vec<String> {
+    xs.iter().map(|x| x.to_string()).collect()
+}
+
+#[test]
+fn assembler_passes_complete_execs() {
+    let mut output = Recorder::default();
+
+    assemble(
+        Some(ExitMode::Separate),
+        vec![
+            exec(42, 1_000, 0, &["/bin/ls", "ls", "-l"]),
+            Event::from_sample(&[1, 0, 0]),
+            exit(42, 2_000, 1_000, 0),
+        ],
+        &mut output,
+    );
+
+    assert_eq!(
+        output.0,
+        vec![("exec", 42, strings(&["ls", "-l"])), ("exit", 42, Vec::new())]
+    );
+}
+
+#[test]
+fn assembler_merges_exits() {
+    let mut output = Recorder::default();
+
+    assemble(
+        Some(ExitMode::Merged),
+        vec![
+            exec(42, 1_000, 0, &["/bin/ls", "ls"]),
+            exec(43, 2_000, 0, &["/bin/sh", "sh"]),
+            // Exec without exit replaced by another program
+            exec(43, 3_000, 0, &["/bin/cat", "cat"]),
+            exit(42, 4_000, 1_000, 0),
+            // Exit of a process started before
+            exit(44, 5_000, 500, 0),
+        ],
+        &mut output,
+    );
+
+    assert_eq!(
+        output.0,
+        vec![
+            ("exec", 43, strings(&["sh"])),
+            ("completed", 42, strings(&["ls"])),
+            ("exit", 44, Vec::new()),
+            ("exec", 43, strings(&["cat"])),
+        ]
+    );
+}
+
+#[test]
+fn assembler_evicts_stale_execs() {
+    let mut output = Recorder::default();
+    let assembler = assembler(Some(ExitMode::Merged)).pending_ttl(Duration::from_secs(10));
+
+    assemble_with(
+        assembler,
+        vec![
+            exec(42, 1_000_000_000, 0, &["/bin/ls", "ls"]),
+            exec(43, 5_000_000_000, 0, &["/bin/sh", "sh"]),
+            exec(44, 12_000_000_000, 0, &["/bin/cat", "cat"]),
+            // Exit of an evicted exec is reported on its own
+            exit(42, 13_000_000_000, 1_000_000_000, 0),
+            exit(43, 14_000_000_000, 5_000_000_000, 0),
+        ],
+        &mut output,
+    );
+
+    assert_eq!(
+        output.0,
+        vec![
+            ("incomplete", 42, strings(&["ls"])),
+            ("exit", 42, Vec::new()),
+            ("completed", 43, strings(&["sh"])),
+            ("exec", 44, strings(&["cat"])),
+        ]
+    );
+}
+
+#[test]
+fn assembler_bounds_pending_execs() {
+    let mut output = Recorder::default();
+    let mut assembler = assembler(Some(ExitMode::Merged)).max_pending(2);
+
+    for pid in 42..46 {
+        assembler
+            .push(exec(pid, pid as u64 * 1_000, 0, &["/bin/ls", "ls"]), &mut output)
+            .unwrap();
+    }
+    assembler.finish(&mut output).unwrap();
+
+    let kinds: Vec<_> = output.0.iter().map(|(kind, pid, _)| (*kind, *pid)).collect();
+    assert_eq!(
+        kinds,
+        vec![("incomplete", 42), ("incomplete", 43), ("exec", 44), ("exec", 45)]
+    );
+    assert_eq!(assembler.evicted(), 2);
+}
+
+#[test]
+fn assembler_counts_dropped_execs() {
+    let mut output = Recorder::default();
+    let mut assembler = assembler(Some(ExitMode::Merged));
+
+    for event in [
+        Event::Dropped(DroppedExecs { count: 3 }),
+        exec(42, 1_000, 0, &["/bin/ls", "ls"]),
+        Event::Dropped(DroppedExecs { count: 2 }),
+    ] {
+        assembler.push(event, &mut output).unwrap();
+    }
+    assembler.finish(&mut output).unwrap();
+
+    assert_eq!(
+        output.0,
+        vec![
+            ("dropped", 3, Vec::new()),
+            ("dropped", 2, Vec::new()),
+            ("exec", 42, strings(&["ls"])),
+        ]
+    );
+    assert_eq!(assembler.dropped(), 5);
+    assert_eq!(assembler.evicted(), 0);
+}
+
+#[test]
+fn legacy_adapter_splits_execs() {
+    let mut output = LegacyAdapter(Recorder::default());
+
+    assemble(
+        Some(ExitMode::Merged),
+        vec![
+            exec(42, 1_000, 0, &["/bin/ls", "ls"]),
+            exec(43, 2_000, 0, &["/bin/sh", "sh"]),
+            exit(42, 3_000, 1_000, 0),
+        ],
+        &mut output,
+    );
+
+    assert_eq!(
+        output.0 .0,
+        vec![("completed", 42, strings(&["ls"])), ("ret", 43, strings(&["sh"]))]
+    );
+}
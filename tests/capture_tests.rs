@@ -0,0 +1,313 @@
+use common::{c_string, exec, exit, new_exec, quiet_opts, replay_json, Buffer};
+use exec_logger::bpf::{self, DroppedExecs, Event, LostEvents};
+use exec_logger::capture::{CaptureReader, CaptureWriter, ReplaySpeed, MAGIC, VERSION};
+use exec_logger::env::EnvOpts;
+use exec_logger::output::{JsonLinesOutput, JsonLinesOutputOpts};
+use exec_logger::source::{CaptureSource, ReplayItem, ReplaySource};
+use exec_logger::{AncestorName, Error, ExecLogger, ExecLoggerOpts, ExitMode};
+use std::io::Cursor;
+use std::time::{Duration, Instant};
+
+mod common;
+
+fn capture(items: Vec<ReplayItem>) -> Vec<u8> {
+    capture_env(EnvOpts::default(), items)
+}
+
+fn capture_env(env: EnvOpts, items: Vec<ReplayItem>) -> Vec<u8> {
+    let buffer = Buffer::default();
+    let mut writer = CaptureWriter::new(buffer.clone()).unwrap().env(env);
+    for item in &items {
+        match item {
+            ReplayItem::Event(event) => writer.write_event(event).unwrap(),
+            ReplayItem::Lost(lost) => writer.write_lost(lost).unwrap(),
+        }
+    }
+    writer.flush().unwrap();
+    let bytes = buffer.0.lock().unwrap().clone();
+    bytes
+}
+
+fn capture_source(bytes: Vec<u8>) -> CaptureSource {
+    CaptureSource::new(CaptureReader::new(Cursor::new(bytes)).unwrap(), ReplaySpeed::Fast)
+}
+
+fn read_error(bytes: Vec<u8>) -> Error {
+    match CaptureReader::new(Cursor::new(bytes)).and_then(|mut x| x.read_record()) {
+        Err(err) => err,
+        Ok(_) => panic!("expected invalid capture"),
+    }
+}
+
+#[test]
+fn capture_round_trip() {
+    let mut sent = new_exec(42, 1_000, 0, &["/bin/ls", "ls", "\u{1b}\u{ff}"]);
+    sent.tid = 44;
+    sent.envs = b"HOME=/root\0".to_vec();
+    sent.envs_truncated = true;
+    sent.tty = c_string("pts/12");
+    let bytes = capture_env(
+        EnvOpts::new(vec!["HOME"]),
+        vec![
+            Event::Exec(sent).into(),
+            exit(42, 5_000, 1_000, 3).into(),
+            LostEvents { cpu: 2, count: 7 }.into(),
+            Event::Dropped(DroppedExecs { count: 4 }).into(),
+            Event::Incomplete(new_exec(43, 2_000, 0, &["/bin/sh", "sh"])).into(),
+        ],
+    );
+
+    let records: Vec<_> = CaptureReader::new(Cursor::new(bytes))
+        .unwrap()
+        .collect::<Result<_, _>>()
+        .unwrap();
+
+    assert_eq!(records.len(), 5);
+    match &records[0].item {
+        ReplayItem::Event(Event::Exec(exec)) => {
+            assert_eq!(exec.pid, 42);
+            assert_eq!(exec.tid, 44);
+            assert_eq!(exec.ts, 1_000);
+            assert_eq!(exec.variant, bpf::ExecVariant::VARIANT_EXECVE);
+            assert_eq!(exec.comm, c_string::<16>("ls"));
+            assert_eq!(exec.tty, c_string::<64>("pts/12"));
+            assert_eq!(bpf::parse_dentry_path(&exec.cwd).unwrap(), "/tmp");
+            assert_eq!(exec.ancestors.len(), 1);
+            assert_eq!(exec.ancestors[0].comm, c_string::<16>("init"));
+            assert_eq!(exec.args, "/bin/ls\0ls\0\u{1b}\u{ff}\0".as_bytes());
+            assert_eq!(exec.envs_count, 1);
+            assert_eq!(exec.envs, b"HOME=/root\0");
+            assert!(exec.envs_truncated);
+        }
+        _ => panic!("expected exec"),
+    }
+    match &records[1].item {
+        ReplayItem::Event(Event::Exit(exit)) => {
+            assert_eq!(exit.pid, 42);
+            assert_eq!(exit.ts, 5_000);
+            assert_eq!(exit.start_ts, 1_000);
+            assert_eq!(exit.exit_code, 3);
+        }
+        _ => panic!("expected exit"),
+    }
+    match &records[2].item {
+        ReplayItem::Lost(lost) => assert_eq!(*lost, LostEvents { cpu: 2, count: 7 }),
+        _ => panic!("expected lost events"),
+    }
+    match &records[3].item {
+        ReplayItem::Event(Event::Dropped(dropped)) => assert_eq!(*dropped, DroppedExecs { count: 4 }),
+        _ => panic!("expected dropped execs"),
+    }
+    match &records[4].item {
+        ReplayItem::Event(Event::Incomplete(exec)) => {
+            assert_eq!(exec.pid, 43);
+            assert_eq!(exec.args, b"/bin/sh\0sh\0");
+        }
+        _ => panic!("expected incomplete exec"),
+    }
+    assert!(records[0].time <= records[2].time);
+}
+
+#[test]
+fn capture_records_selected_env_only() {
+    let sent = || {
+        let mut exec = new_exec(42, 1_000, 0, &["/bin/ls", "ls"]);
+        exec.envs = b"HOME=/root\0AWS_SECRET=hunter2\0AWS_REGION=eu\0PASSWORD=swordfish\0".to_vec();
+        exec.envs_count = 4;
+        Event::Exec(exec)
+    };
+    let env = EnvOpts::new(vec!["HOME", "AWS_*"]).redact(vec!["AWS_SECRET"]);
+
+    let bytes = capture_env(env, vec![sent().into()]);
+
+    let contains = |bytes: &[u8], s: &str| bytes.windows(s.len()).any(|x| x == s.as_bytes());
+    assert!(!contains(&bytes, "hunter2"));
+    assert!(!contains(&bytes, "PASSWORD"));
+    assert!(!contains(&bytes, "swordfish"));
+    let records: Vec<_> = CaptureReader::new(Cursor::new(bytes))
+        .unwrap()
+        .collect::<Result<_, _>>()
+        .unwrap();
+    match &records[0].item {
+        ReplayItem::Event(Event::Exec(exec)) => {
+            assert_eq!(exec.envs_count, 3);
+            assert_eq!(
+                exec.envs,
+                b"AWS_REGION=eu\0AWS_SECRET=<redacted>\0HOME=/root\0".to_vec()
+            );
+        }
+        _ => panic!("expected exec"),
+    }
+
+    // Nothing is recorded unless selected
+    let bytes = capture(vec![sent().into()]);
+    assert!(!contains(&bytes, "HOME"));
+}
+
+#[test]
+fn capture_rejects_invalid_files() {
+    assert!(matches!(
+        read_error(b"not a capture".to_vec()),
+        Error::InvalidCapture { .. }
+    ));
+
+    for version in [0, VERSION + 1] {
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend_from_slice(&u16::to_le_bytes(version));
+        assert!(matches!(read_error(bytes), Error::InvalidCapture { .. }));
+    }
+
+    let mut bytes = capture(vec![exec(42, 1_000, 0, &["/bin/ls", "ls"]).into()]);
+    bytes.truncate(bytes.len() - 1);
+    assert!(matches!(read_error(bytes), Error::InvalidCapture { .. }));
+}
+
+#[test]
+fn record_and_replay_give_the_same_output() {
+    let opts = || ExecLoggerOpts {
+        exits: Some(ExitMode::Merged),
+        ..quiet_opts()
+    };
+    let items = || {
+        vec![
+            exec(42, 1_000, 0, &["/bin/ls", "ls", "-l"]),
+            exec(43, 2_000, 0, &["/bin/sleep", "sleep", "10"]),
+            exit(42, 3_000, 1_000, 0),
+        ]
+    };
+    let recording = Buffer::default();
+    let capture = CaptureWriter::new(recording.clone()).unwrap();
+    let buffer = Buffer::default();
+    let output = JsonLinesOutput::new(JsonLinesOutputOpts::new(buffer.clone(), false, true));
+    ExecLogger::new(opts(), output)
+        .source(ReplaySource::new(items()))
+        .record(capture)
+        .run()
+        .unwrap()
+        .wait()
+        .unwrap();
+    let bytes = recording.0.lock().unwrap().clone();
+
+    let (replayed, _) = replay_json(opts(), capture_source(bytes));
+
+    assert_eq!(replayed, buffer.json_lines());
+    assert_eq!(replayed.len(), 2);
+}
+
+#[test]
+fn record_applies_env_opts() {
+    let opts = || ExecLoggerOpts {
+        env: EnvOpts::new(vec!["HOME", "TOKEN"]).redact(vec!["TOKEN"]),
+        ..quiet_opts()
+    };
+    let mut sent = new_exec(42, 1_000, 0, &["/bin/ls", "ls"]);
+    sent.envs = b"HOME=/root\0TOKEN=hunter2\0PASSWORD=swordfish\0".to_vec();
+    let recording = Buffer::default();
+    let output = JsonLinesOutput::new(JsonLinesOutputOpts::new(Buffer::default(), false, true));
+    ExecLogger::new(opts(), output)
+        .source(ReplaySource::new(vec![Event::Exec(sent)]))
+        .record(CaptureWriter::new(recording.clone()).unwrap())
+        .run()
+        .unwrap()
+        .wait()
+        .unwrap();
+    let bytes = recording.0.lock().unwrap().clone();
+
+    assert!(!bytes.windows(7).any(|x| x == b"hunter2"));
+    assert!(!bytes.windows(9).any(|x| x == b"swordfish"));
+    let (replayed, _) = replay_json(opts(), capture_source(bytes));
+    assert_eq!(
+        replayed[0]["env"],
+        serde_json::json!({"HOME": "/root", "TOKEN": "<redacted>"})
+    );
+}
+
+#[test]
+fn replay_applies_filters() {
+    let mut denied = new_exec(43, 2_000, 0, &["/bin/cat", "cat"]);
+    denied.comm = c_string("cat");
+    let bytes = capture(vec![
+        exec(42, 1_000, 0, &["/bin/ls", "ls", "-a", "-l"]).into(),
+        Event::Exec(denied).into(),
+        exit(43, 3_000, 2_000, 0).into(),
+        exit(42, 4_000, 1_000, 0).into(),
+    ]);
+    let mut opts = quiet_opts();
+    opts.exits = Some(ExitMode::Separate);
+    opts.filters.comm_deny = vec!["cat".to_string()];
+    opts.filters.max_args = Some(2);
+
+    let (lines, _) = replay_json(opts, capture_source(bytes));
+
+    assert_eq!(lines.len(), 2);
+    assert_eq!(lines[0]["pid"], 42);
+    assert_eq!(lines[0]["argv"], serde_json::json!(["ls", "-a"]));
+    assert_eq!(lines[0]["truncated"], true);
+    assert_eq!(lines[1]["event"], "exit");
+    assert_eq!(lines[1]["pid"], 42);
+}
+
+#[test]
+fn replay_applies_filters_to_incomplete_execs() {
+    let mut denied = new_exec(43, 2_000, 0, &["/bin/cat", "cat"]);
+    denied.comm = c_string("cat");
+    let bytes = capture(vec![
+        Event::Incomplete(new_exec(42, 1_000, 0, &["/bin/ls", "ls", "-a", "-l"])).into(),
+        Event::Incomplete(denied).into(),
+    ]);
+    let mut opts = quiet_opts();
+    opts.filters.comm_deny = vec!["cat".to_string()];
+    opts.filters.max_args = Some(2);
+
+    let (lines, stats) = replay_json(opts, capture_source(bytes));
+
+    assert_eq!(lines.len(), 1);
+    assert_eq!(lines[0]["event"], "incomplete");
+    assert_eq!(lines[0]["argv"], serde_json::json!(["ls", "-a"]));
+    assert_eq!(stats.incomplete_execs, 1);
+}
+
+#[test]
+fn replay_matches_ancestors_of_recording() {
+    // The recorded ancestor id refers to the names at the time of recording, so it is ignored
+    let bytes = capture(vec![
+        exec(42, 1_000, 7, &["/bin/ls", "ls"]).into(),
+        exec(43, 2_000, 0, &["/bin/cat", "cat"]).into(),
+    ]);
+    let mut opts = quiet_opts();
+    opts.filters.ancestor_names = vec!["in*=boot".parse::<AncestorName>().unwrap()];
+    opts.filters.only_ancestor = true;
+
+    let (lines, _) = replay_json(opts, capture_source(bytes));
+
+    assert_eq!(lines.len(), 2);
+    assert_eq!(lines[0]["ancestor_name"], "in");
+    assert_eq!(lines[0]["origin"], "boot");
+
+    let bytes = capture(vec![exec(42, 1_000, 7, &["/bin/ls", "ls"]).into()]);
+    let mut opts = quiet_opts();
+    opts.filters.ancestor_names = vec![AncestorName::new("sshd")];
+    opts.filters.only_ancestor = true;
+
+    let (lines, _) = replay_json(opts, capture_source(bytes));
+
+    assert!(lines.is_empty());
+}
+
+#[test]
+fn replay_at_original_speed() {
+    let buffer = Buffer::default();
+    let mut writer = CaptureWriter::new(buffer.clone()).unwrap();
+    writer.write_event(&exec(42, 1_000, 0, &["/bin/ls", "ls"])).unwrap();
+    std::thread::sleep(Duration::from_millis(300));
+    writer.write_event(&exec(43, 2_000, 0, &["/bin/ls", "ls"])).unwrap();
+    writer.flush().unwrap();
+    let bytes = buffer.0.lock().unwrap().clone();
+    let source = CaptureSource::new(CaptureReader::new(Cursor::new(bytes)).unwrap(), ReplaySpeed::Original);
+
+    let start = Instant::now();
+    let (lines, _) = replay_json(quiet_opts(), source);
+
+    assert_eq!(lines.len(), 2);
+    assert!(start.elapsed() >= Duration::from_millis(300));
+}
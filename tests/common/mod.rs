@@ -0,0 +1,140 @@
+//! Helpers shared by the integration tests
+#![allow(dead_code)]
+
+use exec_logger::bpf::{self, Event, EventType, ExecVariant};
+use exec_logger::output::{JsonLinesOutput, JsonLinesOutputOpts, TableOutput, TableOutputOpts};
+use exec_logger::source::EventSource;
+use exec_logger::{ExecLogger, ExecLoggerOpts, Stats};
+use serde_json::Value;
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+
+/// Collects the output written by the logging thread.
+#[derive(Debug, Clone, Default)]
+pub struct Buffer(pub Arc<Mutex<Vec<u8>>>);
+
+impl Buffer {
+    pub fn lines(&self) -> Vec<String> {
+        let buf = self.0.lock().unwrap();
+        String::from_utf8_lossy(&buf).lines().map(str::to_string).collect()
+    }
+
+    pub fn json_lines(&self) -> Vec<Value> {
+        self.lines().iter().map(|x| serde_json::from_str(x).unwrap()).collect()
+    }
+}
+
+impl Write for Buffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+pub fn c_string<const N: usize>(s: &str) -> [u8; N] {
+    let mut buf = [0u8; N];
+    buf[..s.len()].copy_from_slice(s.as_bytes());
+    buf
+}
+
+/// Path components from the leaf up to the root, e.g., `["tmp", "/"]` for `/tmp`
+pub fn dentry_path(names: &[&str]) -> bpf::DentryPath {
+    let mut path = bpf::DentryPath {
+        names: [[0; 64]; 8],
+        incomplete: 0,
+    };
+    for (i, name) in names.iter().enumerate() {
+        path.names[i] = c_string(name);
+    }
+    path
+}
+
+pub fn exec(pid: u32, ts: u64, ancestor: u32, args: &[&str]) -> Event {
+    Event::Exec(new_exec(pid, ts, ancestor, args))
+}
+
+/// Exec of `ls` by uid 1000 in `/tmp` started by init
+pub fn new_exec(pid: u32, ts: u64, ancestor: u32, args: &[&str]) -> bpf::Exec {
+    let mut buf = Vec::new();
+    for arg in args {
+        buf.extend_from_slice(arg.as_bytes());
+        buf.push(0);
+    }
+    bpf::Exec {
+        ts,
+        pid,
+        tid: pid,
+        ppid: 1,
+        ancestor,
+        comm: c_string("ls"),
+        tty: c_string("pts0"),
+        uid: 1000,
+        gid: 1000,
+        ret_val: 0,
+        variant: ExecVariant::VARIANT_EXECVE,
+        dirfd: libc::AT_FDCWD,
+        flags: 0,
+        dir: dentry_path(&[]),
+        cwd: dentry_path(&["tmp", "/"]),
+        args_count: args.len() as i32 - 1,
+        ancestors: vec![bpf::Ancestor {
+            pid: 1,
+            comm: c_string("init"),
+        }],
+        args: buf,
+        args_truncated: false,
+        envs_count: 0,
+        envs: Vec::new(),
+        envs_truncated: false,
+    }
+}
+
+pub fn exit(pid: u32, ts: u64, start_ts: u64, exit_code: i32) -> Event {
+    Event::Exit(bpf::Exit {
+        r#type: EventType::EVENT_EXIT,
+        pid,
+        ts,
+        start_ts,
+        ancestor: 0,
+        exit_code,
+        signal: 0,
+        comm: c_string("ls"),
+        utime: 0,
+        stime: 0,
+        maxrss: 0,
+    })
+}
+
+pub fn quiet_opts() -> ExecLoggerOpts {
+    ExecLoggerOpts {
+        quiet: true,
+        ..ExecLoggerOpts::default()
+    }
+}
+
+pub fn replay_json<S: EventSource + 'static>(opts: ExecLoggerOpts, source: S) -> (Vec<Value>, Stats) {
+    let buffer = Buffer::default();
+    let output = JsonLinesOutput::new(JsonLinesOutputOpts::new(buffer.clone(), false, true));
+    let stats = ExecLogger::new(opts, output)
+        .source(source)
+        .run()
+        .unwrap()
+        .wait()
+        .unwrap();
+    (buffer.json_lines(), stats)
+}
+
+pub fn replay_table<S: EventSource + 'static>(opts: ExecLoggerOpts, only_ancestor: bool, source: S) -> Vec<String> {
+    let buffer = Buffer::default();
+    let output = TableOutput::new(TableOutputOpts::new(buffer.clone(), only_ancestor, true));
+    ExecLogger::new(opts, output)
+        .source(source)
+        .run()
+        .unwrap()
+        .wait()
+        .unwrap();
+    buffer.lines()
+}
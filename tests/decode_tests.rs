@@ -0,0 +1,214 @@
+use byteorder::{NativeEndian, WriteBytesExt};
+use exec_logger::bpf::{self, Event};
+use proptest::prelude::*;
+
+/// Size of `struct data_t` up to `ancestors`, cf. exec_logger.c
+const HEADER_SIZE: usize = 1200;
+/// Size of `struct ancestor_t`, cf. exec_logger.c
+const ANCESTOR_SIZE: usize = 20;
+/// Size of `struct exit_t`, cf. exec_logger.c
+const EXIT_SIZE: usize = 80;
+
+#[derive(Debug, Clone)]
+struct ExecSample {
+    pid: u32,
+    tid: u32,
+    ts: u64,
+    variant: u32,
+    ancestors: Vec<u32>,
+    ancestors_max: u32,
+    args: Vec<u8>,
+    envs: Vec<u8>,
+    cwd_incomplete: bool,
+}
+
+impl ExecSample {
+    /// Lays out the sample like `struct data_t` as submitted by the BPF program.
+    fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        buf.write_u32::<NativeEndian>(0).unwrap(); // type
+        buf.write_u32::<NativeEndian>(self.pid).unwrap();
+        buf.write_u32::<NativeEndian>(self.tid).unwrap();
+        buf.extend_from_slice(&[0; 4]); // padding
+        buf.write_u64::<NativeEndian>(self.ts).unwrap();
+        buf.write_u32::<NativeEndian>(1).unwrap(); // ppid
+        buf.write_u32::<NativeEndian>(0).unwrap(); // ancestor
+        buf.extend_from_slice(&[b'x'; 16]); // comm
+        buf.extend_from_slice(&[0; 64]); // tty
+        buf.write_u32::<NativeEndian>(1000).unwrap(); // uid
+        buf.write_u32::<NativeEndian>(1000).unwrap(); // gid
+        buf.write_i32::<NativeEndian>(0).unwrap(); // ret_val
+        buf.write_u32::<NativeEndian>(self.variant).unwrap();
+        buf.write_i32::<NativeEndian>(-100).unwrap(); // dirfd
+        buf.write_i32::<NativeEndian>(0).unwrap(); // flags
+        buf.extend_from_slice(&[0; 8 * 64]); // dir
+        buf.write_u32::<NativeEndian>(0).unwrap();
+        // cwd from the leaf up: "tmp" followed by the root unless incomplete
+        for name in [&b"tmp"[..], if self.cwd_incomplete { b"" } else { b"/" }] {
+            let mut buf_name = [0; 64];
+            buf_name[..name.len()].copy_from_slice(name);
+            buf.extend_from_slice(&buf_name);
+        }
+        buf.extend_from_slice(&[0; 6 * 64]);
+        buf.write_u32::<NativeEndian>(self.cwd_incomplete as u32).unwrap();
+        buf.write_i32::<NativeEndian>(1).unwrap(); // args_count
+        buf.write_u32::<NativeEndian>(self.args.len() as u32).unwrap();
+        buf.write_u32::<NativeEndian>(self.ancestors.len() as u32).unwrap();
+        buf.write_u32::<NativeEndian>(self.ancestors_max).unwrap();
+        buf.write_u32::<NativeEndian>(0).unwrap(); // envs_count
+        buf.write_u32::<NativeEndian>(self.envs.len() as u32).unwrap();
+        buf.write_u32::<NativeEndian>(0).unwrap(); // args_truncated
+        buf.write_u32::<NativeEndian>(1).unwrap(); // envs_truncated
+        assert_eq!(buf.len(), HEADER_SIZE);
+
+        for i in 0..self.ancestors_max as usize {
+            let pid = self.ancestors.get(i).copied().unwrap_or(0);
+            buf.write_u32::<NativeEndian>(pid).unwrap();
+            buf.extend_from_slice(&[b'a'; 16]);
+        }
+        assert_eq!(buf.len(), HEADER_SIZE + self.ancestors_max as usize * ANCESTOR_SIZE);
+        buf.extend_from_slice(&self.args);
+        buf.extend_from_slice(&self.envs);
+        buf
+    }
+}
+
+fn exec_sample() -> impl Strategy<Value = ExecSample> {
+    (
+        any::<u32>(),
+        any::<u32>(),
+        any::<u64>(),
+        0u32..3,
+        prop::collection::vec(any::<u32>(), 0..8),
+        0u32..8,
+        prop::collection::vec(any::<u8>(), 0..256),
+        prop::collection::vec(any::<u8>(), 0..256),
+        any::<bool>(),
+    )
+        .prop_map(
+            |(pid, tid, ts, variant, ancestors, extra, args, envs, cwd_incomplete)| ExecSample {
+                pid,
+                tid,
+                ts,
+                variant,
+                ancestors_max: ancestors.len() as u32 + extra,
+                ancestors,
+                args,
+                envs,
+                cwd_incomplete,
+            },
+        )
+}
+
+fn exit_sample(pid: u32, ts: u64, exit_code: i32, maxrss: u64) -> Vec<u8> {
+    let mut buf = Vec::new();
+    buf.write_u32::<NativeEndian>(1).unwrap(); // type
+    buf.write_u32::<NativeEndian>(pid).unwrap();
+    buf.write_u64::<NativeEndian>(ts).unwrap();
+    buf.write_u64::<NativeEndian>(ts / 2).unwrap(); // start_ts
+    buf.write_u32::<NativeEndian>(0).unwrap(); // ancestor
+    buf.write_i32::<NativeEndian>(exit_code).unwrap();
+    buf.write_i32::<NativeEndian>(0).unwrap(); // signal
+    buf.extend_from_slice(&[b'x'; 16]); // comm
+    buf.extend_from_slice(&[0; 4]); // padding
+    buf.write_u64::<NativeEndian>(1).unwrap(); // utime
+    buf.write_u64::<NativeEndian>(2).unwrap(); // stime
+    buf.write_u64::<NativeEndian>(maxrss).unwrap();
+    assert_eq!(buf.len(), EXIT_SIZE);
+    buf
+}
+
+proptest! {
+    #[test]
+    fn decode_arbitrary_bytes_does_not_panic(bytes in prop::collection::vec(any::<u8>(), 0..2048)) {
+        let _ = Event::decode(&bytes);
+    }
+
+    #[test]
+    fn decode_arbitrary_exec_bodies_does_not_panic(
+        sample in exec_sample(),
+        body in prop::collection::vec(any::<u8>(), 0..2048),
+    ) {
+        let mut bytes = sample.encode();
+        bytes.truncate(HEADER_SIZE);
+        bytes.extend(body);
+        let _ = Event::decode(&bytes);
+    }
+
+    #[test]
+    fn decode_exec(sample in exec_sample()) {
+        let exec = match Event::decode(&sample.encode()) {
+            Ok(Event::Exec(exec)) => exec,
+            _ => return Err(TestCaseError::fail("expected exec event")),
+        };
+        prop_assert_eq!(exec.pid, sample.pid);
+        prop_assert_eq!(exec.tid, sample.tid);
+        prop_assert_eq!(exec.ts, sample.ts);
+        prop_assert_eq!(exec.variant as u32, sample.variant);
+        prop_assert_eq!(exec.ancestors.iter().map(|x| x.pid).collect::<Vec<_>>(), sample.ancestors);
+        prop_assert_eq!(exec.args, sample.args);
+        prop_assert_eq!(exec.envs, sample.envs);
+        prop_assert!(!exec.args_truncated);
+        prop_assert!(exec.envs_truncated);
+        let cwd = bpf::parse_dentry_path(&exec.cwd).unwrap();
+        prop_assert_eq!(cwd, if sample.cwd_incomplete { ".../tmp" } else { "/tmp" });
+    }
+
+    #[test]
+    fn decode_exec_with_trailing_bytes(sample in exec_sample(), padding in 0usize..8) {
+        let mut bytes = sample.encode();
+        bytes.extend(vec![0; padding]);
+        prop_assert!(matches!(Event::decode(&bytes), Ok(Event::Exec(_))));
+    }
+
+    #[test]
+    fn decode_truncated_exec_fails(sample in exec_sample(), cut in any::<prop::sample::Index>()) {
+        let bytes = sample.encode();
+        let len = cut.index(bytes.len());
+        prop_assert!(Event::decode(&bytes[..len]).is_err());
+    }
+
+    #[test]
+    fn decode_exec_with_invalid_variant_fails(sample in exec_sample(), variant in 3u32..) {
+        let sample = ExecSample { variant, ..sample };
+        prop_assert!(Event::decode(&sample.encode()).is_err());
+    }
+
+    #[test]
+    fn decode_exec_with_too_many_ancestors_fails(sample in exec_sample(), extra in 1u32..16) {
+        let mut bytes = sample.encode();
+        // ancestors_count follows args_count and args_size
+        let offset = HEADER_SIZE - 6 * 4;
+        let count = sample.ancestors_max + extra;
+        bytes[offset..offset + 4].copy_from_slice(&count.to_ne_bytes());
+        prop_assert!(Event::decode(&bytes).is_err());
+    }
+
+    #[test]
+    fn decode_invalid_event_type_fails(r#type in 2u32.., body in prop::collection::vec(any::<u8>(), 0..2048)) {
+        let mut bytes = r#type.to_ne_bytes().to_vec();
+        bytes.extend(body);
+        prop_assert!(Event::decode(&bytes).is_err());
+    }
+
+    #[test]
+    fn decode_exit(pid in any::<u32>(), ts in any::<u64>(), exit_code in any::<i32>(), maxrss in any::<u64>()) {
+        let exit = match Event::decode(&exit_sample(pid, ts, exit_code, maxrss)) {
+            Ok(Event::Exit(exit)) => exit,
+            _ => return Err(TestCaseError::fail("expected exit event")),
+        };
+        prop_assert_eq!(exit.pid, pid);
+        prop_assert_eq!(exit.ts, ts);
+        prop_assert_eq!(exit.start_ts, ts / 2);
+        prop_assert_eq!(exit.exit_code, exit_code);
+        prop_assert_eq!(exit.comm, [b'x'; 16]);
+        prop_assert_eq!(exit.utime, 1);
+        prop_assert_eq!(exit.stime, 2);
+        prop_assert_eq!(exit.maxrss, maxrss);
+    }
+
+    #[test]
+    fn decode_truncated_exit_fails(len in 0usize..EXIT_SIZE) {
+        prop_assert!(Event::decode(&exit_sample(1, 2, 3, 4)[..len]).is_err());
+    }
+}
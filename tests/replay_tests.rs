@@ -0,0 +1,290 @@
+use common::{c_string, exec, exit, new_exec, quiet_opts, replay_json, replay_table, Buffer};
+use exec_logger::bpf::{DroppedExecs, Event, LostEvents};
+use exec_logger::output::{JsonLinesOutput, JsonLinesOutputOpts, TimeFormat};
+use exec_logger::source::{ReplayItem, ReplaySource};
+use exec_logger::{AncestorName, Error, ExecLogger, ExecLoggerOpts, ExitMode, Stats};
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+
+mod common;
+
+#[test]
+fn replay_exec_to_json_lines() {
+    let source = ReplaySource::new(vec![exec(42, 1_000, 0, &["/bin/ls", "ls", "-l"])]);
+
+    let (lines, stats) = replay_json(quiet_opts(), source);
+
+    assert_eq!(lines.len(), 1);
+    let line = &lines[0];
+    assert_eq!(line["event"], "exec");
+    assert_eq!(line["pid"], 42);
+    assert_eq!(line["tid"], 42);
+    assert_eq!(line["comm"], "ls");
+    assert_eq!(line["filename"], "/bin/ls");
+    assert_eq!(line["argv"], serde_json::json!(["ls", "-l"]));
+    assert_eq!(line["argc"], 2);
+    assert_eq!(line["cwd"], "/tmp");
+    assert_eq!(line["cwd_from_procfs"], false);
+    assert_eq!(line["path"], "/bin/ls");
+    assert_eq!(line["ancestor"], false);
+    assert_eq!(stats, Stats::default());
+}
+
+#[test]
+fn replay_does_not_read_cwd_from_procfs() {
+    // Exec of this very process, so procfs would resolve its cwd
+    let mut exec = new_exec(std::process::id(), 1_000, 0, &["/bin/ls", "ls"]);
+    let comm = std::fs::read_to_string("/proc/self/comm").unwrap();
+    exec.comm = c_string(comm.trim_end());
+    exec.cwd.incomplete = 1;
+    let source = ReplaySource::new(vec![Event::Exec(exec)]);
+
+    let (lines, _) = replay_json(quiet_opts(), source);
+
+    assert_eq!(lines[0]["cwd"], ".../tmp");
+    assert_eq!(lines[0]["cwd_from_procfs"], false);
+}
+
+#[test]
+fn replay_merges_exec_and_exit() {
+    let opts = ExecLoggerOpts {
+        exits: Some(ExitMode::Merged),
+        ..quiet_opts()
+    };
+    let source = ReplaySource::new(vec![
+        exec(42, 1_000, 0, &["/bin/ls", "ls"]),
+        exec(43, 2_000, 0, &["/bin/sleep", "sleep", "10"]),
+        exit(42, 3_000_000_000, 1_000, 3),
+    ]);
+
+    let (lines, _) = replay_json(opts, source);
+
+    assert_eq!(lines.len(), 2);
+    assert_eq!(lines[0]["event"], "completed");
+    assert_eq!(lines[0]["pid"], 42);
+    assert_eq!(lines[0]["exit_code"], 3);
+    // Still running when the replay ends
+    assert_eq!(lines[1]["event"], "exec");
+    assert_eq!(lines[1]["pid"], 43);
+}
+
+#[test]
+fn replay_merges_exec_by_thread_with_exit_of_process() {
+    let opts = ExecLoggerOpts {
+        exits: Some(ExitMode::Merged),
+        ..quiet_opts()
+    };
+    // The thread 43 of process 42 exec'ed and continues as 42, cf. de_thread
+    let mut by_thread = new_exec(42, 1_000, 0, &["/bin/ls", "ls"]);
+    by_thread.tid = 43;
+    let source = ReplaySource::new(vec![Event::Exec(by_thread), exit(42, 2_000, 1_000, 0)]);
+
+    let (lines, _) = replay_json(opts, source);
+
+    assert_eq!(lines.len(), 1);
+    assert_eq!(lines[0]["event"], "completed");
+    assert_eq!(lines[0]["pid"], 42);
+    assert_eq!(lines[0]["tid"], 43);
+}
+
+#[test]
+fn replay_reports_incomplete_execs() {
+    let opts = ExecLoggerOpts {
+        exits: Some(ExitMode::Merged),
+        max_pending: 1,
+        ..quiet_opts()
+    };
+    let source = ReplaySource::new(vec![
+        exec(42, 1_000, 0, &["/bin/ls", "ls"]),
+        exec(43, 2_000, 0, &["/bin/sleep", "sleep", "10"]),
+    ]);
+
+    let (lines, stats) = replay_json(opts, source);
+
+    assert_eq!(lines.len(), 2);
+    assert_eq!(lines[0]["event"], "incomplete");
+    assert_eq!(lines[0]["pid"], 42);
+    assert_eq!(lines[1]["event"], "exec");
+    assert_eq!(stats.incomplete_execs, 1);
+}
+
+#[test]
+fn replay_resolves_ancestor_names_of_filters() {
+    let mut opts = quiet_opts();
+    opts.filters.ancestor_names = vec![AncestorName::new("cron"), AncestorName::new("sshd").label("ssh")];
+    let source = ReplaySource::new(vec![
+        exec(42, 1_000, 2, &["/bin/ls", "ls"]),
+        exec(43, 2_000, 0, &["/bin/cat", "cat"]),
+    ]);
+
+    let (lines, _) = replay_json(opts, source);
+
+    assert_eq!(lines[0]["ancestor"], true);
+    assert_eq!(lines[0]["ancestor_name"], "sshd");
+    assert_eq!(lines[0]["origin"], "ssh");
+    assert_eq!(lines[1]["ancestor"], false);
+}
+
+#[test]
+fn replay_only_ancestor_to_table() {
+    let mut opts = quiet_opts();
+    opts.filters.ancestor_names = vec![AncestorName::new("sshd")];
+    let source = ReplaySource::new(vec![
+        exec(42, 1_000, 1, &["/bin/ls", "ls"]),
+        exec(43, 2_000, 0, &["/bin/cat", "cat"]),
+    ]);
+
+    let lines = replay_table(opts, true, source);
+
+    assert_eq!(lines.len(), 1);
+    assert!(lines[0].contains("sshd"));
+    assert!(lines[0].ends_with("/bin/ls"));
+}
+
+#[test]
+fn replay_escapes_control_characters_in_table() {
+    let source = ReplaySource::new(vec![exec(42, 1_000, 0, &["/bin/echo", "echo", "\x1b[2J"])]);
+
+    let lines = replay_table(quiet_opts(), false, source);
+
+    assert_eq!(lines.len(), 1);
+    assert!(!lines[0].contains('\x1b'));
+    assert!(lines[0].ends_with("/bin/echo \\x1b[2J"));
+}
+
+#[test]
+fn replay_counts_lost_and_malformed_events() {
+    let source = ReplaySource::new(vec![
+        ReplayItem::Lost(LostEvents { cpu: 1, count: 5 }),
+        ReplayItem::Event(Event::from_sample(&[1, 0, 0])),
+        ReplayItem::Event(exec(42, 1_000, 0, &["/bin/ls", "ls"])),
+        ReplayItem::Lost(LostEvents { cpu: 1, count: 2 }),
+    ]);
+
+    let (lines, stats) = replay_json(quiet_opts(), source);
+
+    assert_eq!(lines.len(), 3);
+    assert_eq!(lines[0]["event"], "lost");
+    assert_eq!(lines[0]["count"], 5);
+    assert_eq!(lines[1]["event"], "exec");
+    assert_eq!(stats.lost_events, 7);
+    assert_eq!(stats.lost_events_per_cpu.get(&1), Some(&7));
+    assert_eq!(stats.malformed_events, 1);
+}
+
+#[test]
+fn replay_counts_dropped_execs_as_incomplete() {
+    let opts = ExecLoggerOpts {
+        exits: Some(ExitMode::Merged),
+        max_pending: 1,
+        ..quiet_opts()
+    };
+    let source = ReplaySource::new(vec![
+        Event::Dropped(DroppedExecs { count: 3 }),
+        exec(42, 1_000, 0, &["/bin/ls", "ls"]),
+        exec(43, 2_000, 0, &["/bin/ls", "ls"]),
+    ]);
+
+    let (lines, stats) = replay_json(opts, source);
+
+    assert_eq!(lines[0]["event"], "dropped");
+    assert_eq!(lines[0]["count"], 3);
+    assert_eq!(lines[1]["event"], "incomplete");
+    assert_eq!(stats.incomplete_execs, 4);
+
+    let source = ReplaySource::new(vec![Event::Dropped(DroppedExecs { count: 2 })]);
+    let lines = replay_table(quiet_opts(), false, source);
+    assert_eq!(lines, vec!["*** dropped 2 incomplete execs ***"]);
+}
+
+#[test]
+fn replay_reports_execs_with_missed_return_as_incomplete() {
+    let source = ReplaySource::new(vec![
+        Event::Incomplete(new_exec(42, 1_000, 0, &["/bin/ls", "ls", "-l"])),
+        Event::Dropped(DroppedExecs { count: 1 }),
+    ]);
+
+    let (lines, stats) = replay_json(quiet_opts(), source);
+
+    assert_eq!(lines.len(), 2);
+    assert_eq!(lines[0]["event"], "incomplete");
+    assert_eq!(lines[0]["pid"], 42);
+    assert_eq!(lines[0]["argv"], serde_json::json!(["ls", "-l"]));
+    assert_eq!(lines[1]["event"], "dropped");
+    assert_eq!(stats.incomplete_execs, 2);
+}
+
+#[test]
+fn replay_counts_relative_times_from_first_event() {
+    let buffer = Buffer::default();
+    let output =
+        JsonLinesOutput::new(JsonLinesOutputOpts::new(buffer.clone(), false, true).time_format(TimeFormat::Relative));
+    let source = ReplaySource::new(vec![
+        exec(42, 5_000_000_000, 0, &["/bin/ls", "ls"]),
+        exit(42, 7_500_000_000, 5_000_000_000, 0),
+    ]);
+
+    ExecLogger::new(quiet_opts(), output)
+        .source(source)
+        .run()
+        .unwrap()
+        .wait()
+        .unwrap();
+
+    let lines = buffer.json_lines();
+    assert_eq!(lines[0]["time"], 0.0);
+    assert_eq!(lines[1]["time"], 2.5);
+    assert_eq!(lines[1]["exec_time"], 0.0);
+}
+
+#[test]
+fn replay_decodes_samples() {
+    let source = ReplaySource::from_samples(vec![vec![0u8; 8], vec![7, 0, 0, 0]]);
+
+    let (lines, stats) = replay_json(quiet_opts(), source);
+
+    assert!(lines.is_empty());
+    assert_eq!(stats.malformed_events, 2);
+}
+
+/// Accepts `capacity` writes and fails afterwards like a closed pipe.
+#[derive(Clone)]
+struct FailingWriter {
+    writes: Arc<Mutex<usize>>,
+    capacity: usize,
+}
+
+impl Write for FailingWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut writes = self.writes.lock().unwrap();
+        *writes += 1;
+        if *writes > self.capacity {
+            return Err(io::Error::new(io::ErrorKind::BrokenPipe, "closed"));
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn replay_stops_with_error_if_output_fails() {
+    let writer = FailingWriter {
+        writes: Arc::new(Mutex::new(0)),
+        capacity: 1,
+    };
+    let output = JsonLinesOutput::new(JsonLinesOutputOpts::new(writer.clone(), false, true));
+    let source = ReplaySource::new((0..10).map(|i| exec(42 + i, 1_000 * i as u64, 0, &["/bin/ls", "ls"])));
+
+    let res = ExecLogger::new(quiet_opts(), output)
+        .source(source)
+        .run()
+        .unwrap()
+        .wait();
+
+    assert!(matches!(res, Err(Error::IoError { .. })), "{:?}", res);
+    // The output is not written to anymore after the first failure
+    assert_eq!(*writer.writes.lock().unwrap(), 2);
+}